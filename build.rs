@@ -1,8 +1,59 @@
 // build.rs
 
+// Sources cbindgen reads for the C API header: dtln_capi.rs and the files
+// defining the types it uses. Parsing only these keeps the TFLite bindings and
+// other crate internals out of the header.
+const C_API_SOURCES: [&str; 3] = ["src/dtln_capi.rs", "src/constants.rs", "src/dtln_engine.rs"];
+
+// Generate the C API header into OUT_DIR. The checked-in include/dtln_rs.h is
+// compared against it by a test in dtln_capi.rs, and only overwritten when
+// DTLN_UPDATE_HEADER=1 is set.
+fn generate_c_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=DTLN_UPDATE_HEADER");
+
+    let config = match cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("cargo:warning=Failed to read cbindgen.toml: {}", e);
+            return;
+        }
+    };
+    let mut builder = cbindgen::Builder::new().with_config(config);
+    for source in C_API_SOURCES {
+        println!("cargo:rerun-if-changed={}", source);
+        builder = builder.with_src(format!("{}/{}", crate_dir, source));
+    }
+
+    match builder.generate() {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/dtln_rs.h", out_dir));
+            if std::env::var("DTLN_UPDATE_HEADER").is_ok_and(|v| v != "0") {
+                bindings.write_to_file(format!("{}/include/dtln_rs.h", crate_dir));
+            }
+        }
+        Err(e) => println!("cargo:warning=Failed to generate dtln_rs.h: {}", e),
+    }
+}
+
+// wasm32-unknown-unknown builds use the native inference path without TFLite.
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+fn target_has_tflite() -> bool {
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    !(arch == "wasm32" && os == "unknown")
+}
+
 #[cfg(target_os = "windows")]
 fn main() {
     use std::env;
+    generate_c_header();
+    if !target_has_tflite() {
+        return;
+    }
+
     let root_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
     
     // Указываем Cargo, где искать нашу сгенерированную .lib библиотеку
@@ -16,5 +67,88 @@ fn main() {
 // Код для macOS оставляем без изменений
 #[cfg(target_os = "macos")]
 fn main() {
+    generate_c_header();
     // ... ваш код для macOS ...
 }
+
+// Linux looks for tensorflowlite_c in the following order:
+//
+// 1. TFLITE_LIB_DIR: a directory containing a prebuilt libtensorflowlite_c.{a,so}.
+// 2. pkg-config: a system install providing tensorflowlite_c.pc.
+// 3. TFLITE_SOURCE_DIR: a tensorflow checkout, from which tensorflow/lite/c is
+//    built with cmake.
+// 4. <crate>/tflite_lib, mirroring the Windows layout.
+//
+// TFLITE_STATIC=1 links a prebuilt library statically, otherwise it is linked
+// dynamically. Libraries built from source are always linked dynamically.
+#[cfg(target_os = "linux")]
+fn main() {
+    use std::env;
+    use std::path::PathBuf;
+
+    generate_c_header();
+    if !target_has_tflite() {
+        return;
+    }
+    println!("cargo:rerun-if-env-changed=TFLITE_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=TFLITE_SOURCE_DIR");
+    println!("cargo:rerun-if-env-changed=TFLITE_STATIC");
+
+    let link_static = env::var("TFLITE_STATIC").is_ok_and(|v| v != "0");
+    let link_kind = if link_static { "static" } else { "dylib" };
+    let target_linux = env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os == "linux");
+    if !link_static && target_linux {
+        // Look for a prebuilt library next to the binary first, where
+        // scripts/install-linux.js places it beside index.node.
+        println!("cargo:rustc-link-arg=-Wl,-rpath,$ORIGIN");
+    }
+
+    // A static libtensorflowlite_c.a leaves the C++ runtime to the final
+    // link, which rustc drives with cc rather than c++.
+    let link_cpp_runtime = || {
+        if link_static {
+            println!("cargo:rustc-link-lib=dylib=stdc++");
+        }
+    };
+
+    if let Ok(lib_dir) = env::var("TFLITE_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={}", lib_dir);
+        println!("cargo:rustc-link-lib={}=tensorflowlite_c", link_kind);
+        link_cpp_runtime();
+        return;
+    }
+
+    if pkg_config::Config::new()
+        .statik(link_static)
+        .probe("tensorflowlite_c")
+        .is_ok()
+    {
+        return;
+    }
+
+    if let Ok(source_dir) = env::var("TFLITE_SOURCE_DIR") {
+        let tflite_c_dir = PathBuf::from(source_dir).join("tensorflow/lite/c");
+        println!("cargo:rerun-if-changed={}", tflite_c_dir.display());
+
+        // Build the shared library so the transitive static dependencies
+        // (absl, ruy, XNNPACK, ...) are already resolved inside it.
+        let dst = cmake::Config::new(&tflite_c_dir)
+            .define("TFLITE_C_BUILD_SHARED_LIBS", "ON")
+            .profile("Release")
+            .build_target("tensorflowlite_c")
+            .build();
+
+        // The tensorflowlite_c target has no install rule, so link straight
+        // from the build tree.
+        let build_dir = dst.join("build");
+        println!("cargo:rustc-link-search=native={}", build_dir.display());
+        println!("cargo:rustc-link-lib=dylib=tensorflowlite_c");
+        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", build_dir.display());
+        return;
+    }
+
+    let root_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rustc-link-search=native={}/tflite_lib", root_dir);
+    println!("cargo:rustc-link-lib={}=tensorflowlite_c", link_kind);
+    link_cpp_runtime();
+}
@@ -0,0 +1,122 @@
+// Exercises the C API of dtln-rs through include/dtln_rs.h. Run with
+// `make -C c test` after `cargo build --release`.
+
+#include <math.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "dtln_rs.h"
+
+#define SAMPLE_RATE 16000
+#define BLOCK_SIZE 512
+
+static int failures = 0;
+
+#define CHECK(condition)                                              \
+  do {                                                                \
+    if (!(condition)) {                                               \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+              #condition);                                            \
+      failures++;                                                     \
+    }                                                                 \
+  } while (0)
+
+#define CHECK_STATUS(call, expected)                                      \
+  do {                                                                    \
+    DtlnStatus status = (call);                                           \
+    if (status != (expected)) {                                           \
+      fprintf(stderr, "%s:%d: %s returned \"%s\"\n", __FILE__, __LINE__, \
+              #call, dtln_status_string(status));                         \
+      failures++;                                                         \
+    }                                                                     \
+  } while (0)
+
+static void test_errors(void) {
+  DtlnEngine *engine = NULL;
+  float samples[DTLN_BLOCK_SHIFT] = {0};
+
+  CHECK(dtln_abi_version() == DTLN_ABI_VERSION);
+  CHECK_STATUS(dtln_config_default(NULL), DTLN_STATUS_NULL_POINTER);
+  CHECK_STATUS(dtln_engine_new(NULL, NULL), DTLN_STATUS_NULL_POINTER);
+  CHECK_STATUS(dtln_engine_process(NULL, samples, samples, DTLN_BLOCK_SHIFT),
+               DTLN_STATUS_NULL_POINTER);
+  CHECK(dtln_engine_latency(NULL) == 0);
+  dtln_engine_free(NULL);
+
+  DtlnConfig config = {0};
+  CHECK_STATUS(dtln_config_default(&config), DTLN_STATUS_INVALID_ARGUMENT);
+  config.struct_size = sizeof(DtlnConfig);
+  CHECK_STATUS(dtln_config_default(&config), DTLN_STATUS_OK);
+  config.native = true;
+  config.mix = NAN;
+  CHECK_STATUS(dtln_engine_new(&config, &engine), DTLN_STATUS_INVALID_ARGUMENT);
+  CHECK(engine == NULL);
+  config.mix = 1.0f;
+  CHECK_STATUS(dtln_engine_new(&config, &engine), DTLN_STATUS_OK);
+  config.min_gain_db = NAN;
+  CHECK_STATUS(dtln_engine_configure(engine, &config), DTLN_STATUS_INVALID_ARGUMENT);
+  CHECK_STATUS(dtln_engine_process(engine, samples, samples, DTLN_BLOCK_SHIFT - 1),
+               DTLN_STATUS_INVALID_ARGUMENT);
+  CHECK_STATUS(dtln_engine_process(engine, NULL, samples, DTLN_BLOCK_SHIFT),
+               DTLN_STATUS_NULL_POINTER);
+  CHECK_STATUS(dtln_engine_configure(engine, NULL), DTLN_STATUS_NULL_POINTER);
+  dtln_engine_free(engine);
+
+  CHECK(strcmp(dtln_status_string(DTLN_STATUS_OK), "ok") == 0);
+  CHECK(strcmp(dtln_status_string(100), "unknown status") == 0);
+}
+
+static void test_denoise(void) {
+  DtlnConfig config = {.struct_size = sizeof(DtlnConfig)};
+  CHECK_STATUS(dtln_config_default(&config), DTLN_STATUS_OK);
+  config.native = true;
+
+  DtlnEngine *engine = NULL;
+  CHECK_STATUS(dtln_engine_new(&config, &engine), DTLN_STATUS_OK);
+  if (engine == NULL) {
+    return;
+  }
+  CHECK(dtln_engine_latency(engine) == DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT);
+
+  config.limiter_enabled = true;
+  config.agc_enabled = true;
+  config.high_pass_hz = 80.0f;
+  CHECK_STATUS(dtln_engine_configure(engine, &config), DTLN_STATUS_OK);
+  CHECK(dtln_engine_latency(engine) > DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT);
+
+  // A tone in deterministic noise, denoised in place one block at a time.
+  unsigned int seed = 1;
+  float peak = 0.0f;
+  for (int block = 0; block < SAMPLE_RATE / BLOCK_SIZE; block++) {
+    float samples[BLOCK_SIZE];
+    for (int i = 0; i < BLOCK_SIZE; i++) {
+      int n = block * BLOCK_SIZE + i;
+      seed = seed * 1103515245u + 12345u;
+      float noise = ((seed >> 16) & 0x7fff) / 32768.0f - 0.5f;
+      samples[i] = 0.2f * sinf(2.0f * 3.14159265f * 440.0f * n / SAMPLE_RATE) + 0.05f * noise;
+    }
+    CHECK_STATUS(dtln_engine_process(engine, samples, samples, BLOCK_SIZE), DTLN_STATUS_OK);
+    for (int i = 0; i < BLOCK_SIZE; i++) {
+      CHECK(isfinite(samples[i]));
+      peak = fmaxf(peak, fabsf(samples[i]));
+    }
+  }
+
+  // The limiter holds its default -1 dB ceiling.
+  CHECK(peak > 0.0f);
+  CHECK(peak <= powf(10.0f, -1.0f / 20.0f));
+
+  dtln_engine_free(engine);
+}
+
+int main(void) {
+  test_errors();
+  test_denoise();
+
+  if (failures > 0) {
+    fprintf(stderr, "%d checks failed\n", failures);
+    return 1;
+  }
+  printf("All C API checks passed\n");
+  return 0;
+}
@@ -0,0 +1,182 @@
+// Types of the interface shared by the Node module (dtln.node.js) and the
+// WASM module (dtln_post.js). Members only one of them provides are optional.
+
+/** A denoiser created by dtln_create. */
+export type DtlnHandle = unknown;
+/** A pipeline created by dtln_pipeline_create. */
+export type DtlnPipelineHandle = unknown;
+
+export interface DtlnCreateOptions {
+  /**
+   * "deferred" (the default) denoises on a background thread and returns the
+   * output of the previous call, "immediate" denoises on the calling thread.
+   * Node only, WASM always denoises immediately.
+   */
+  mode?: "immediate" | "deferred";
+  /** Sample rate of the samples, 16000 by default. Other rates are resampled. */
+  sampleRate?: number;
+  /** TFLite interpreter threads. Node only. */
+  threads?: number;
+  /** Use the native model implementation instead of TFLite. */
+  native?: boolean;
+  /** Suppression level, the minimum gain of the suppression mask. */
+  minGainDb?: number;
+}
+
+export interface DtlnAgcOptions {
+  targetLevelDb?: number;
+  maxGainDb?: number;
+  attackMs?: number;
+  releaseMs?: number;
+  /** Only adapt the gain while speech is detected, true by default. */
+  vadGated?: boolean;
+}
+
+export interface DtlnLimiterOptions {
+  ceilingDb?: number;
+  lookaheadMs?: number;
+  releaseMs?: number;
+}
+
+/** Omitted filters are disabled. */
+export interface DtlnPreFilterOptions {
+  dcBlocker?: boolean;
+  highPassHz?: number;
+}
+
+export interface DtlnVad {
+  speechProbability: number;
+  isSpeech: boolean;
+}
+
+export interface DtlnClipStats {
+  /** Output samples beyond +-1.0. */
+  clippedSamples: number;
+  /** Output samples attenuated by the limiter. */
+  limitedSamples: number;
+}
+
+export interface DtlnStats {
+  calls: number;
+  samples: number;
+  starvedCalls: number;
+  processingTimeMs: number;
+  maxProcessingTimeMs: number;
+  /** Processing time divided by the duration of the samples. */
+  realtimeFactor: number;
+}
+
+export type DtlnStageConfig =
+  | { type: "dc_blocker" }
+  | { type: "high_pass"; cutoff_hz?: number }
+  | { type: "gain"; db?: number }
+  | { type: "denoise"; native?: boolean; min_gain_db?: number; mix?: number }
+  | {
+      type: "agc";
+      target_level_db?: number;
+      max_gain_db?: number;
+      attack_ms?: number;
+      release_ms?: number;
+      vad_gated?: boolean;
+    }
+  | { type: "limiter"; ceiling_db?: number; lookahead_ms?: number; release_ms?: number };
+
+/** The config format of --pipeline, see audio_pipeline.rs. */
+export interface DtlnPipelineConfig {
+  sample_rate: number;
+  stages: DtlnStageConfig[];
+}
+
+export interface DtlnOfflineOptions {
+  native?: boolean;
+  segmentSeconds?: number;
+  threads?: number;
+}
+
+export interface DtlnFileResult {
+  durationSeconds: number;
+  realtimeFactor: number;
+}
+
+export declare function dtln_create(options?: DtlnCreateOptions): DtlnHandle;
+/** Stop and free the denoiser. Calling it again has no effect. */
+export declare function dtln_destroy(handle: DtlnHandle): void;
+/** The same as dtln_destroy. */
+export declare function dtln_stop(handle: DtlnHandle): void;
+/**
+ * Denoise samples into output. At 16khz, Node takes multiples of 128 samples.
+ * WASM buffers partial blocks and takes any length.
+ * @returns True if the deferred processing thread is backed up.
+ */
+export declare function dtln_denoise(handle: DtlnHandle, samples: Float32Array, output: Float32Array): boolean;
+/** Return the denoiser to its initial state, keeping its settings. */
+export declare function dtln_reset(handle: DtlnHandle): void;
+/**
+ * Delay of the output in samples, at the denoiser sample rate. In deferred
+ * mode the output is also one dtln_denoise call behind, which is not included.
+ */
+export declare function dtln_latency(handle: DtlnHandle): number;
+export declare function dtln_stats(handle: DtlnHandle, reset?: boolean): DtlnStats;
+export declare function dtln_set_min_gain_db(handle: DtlnHandle, minGainDb: number): void;
+/** 1.0 outputs only the denoised signal, 0.0 only the input. */
+export declare function dtln_set_mix(handle: DtlnHandle, wet: number): void;
+/** Voice activity of the most recent dtln_denoise call. */
+export declare function dtln_vad(handle: DtlnHandle): DtlnVad;
+/** null disables the AGC. */
+export declare function dtln_set_agc(handle: DtlnHandle, options: DtlnAgcOptions | null): void;
+/** null disables the limiter. */
+export declare function dtln_set_limiter(handle: DtlnHandle, options: DtlnLimiterOptions | null): void;
+export declare function dtln_set_pre_filter(handle: DtlnHandle, options: DtlnPreFilterOptions | null): void;
+export declare function dtln_clip_stats(handle: DtlnHandle, reset?: boolean): DtlnClipStats;
+
+/** config is a pipeline config or its JSON. */
+export declare function dtln_pipeline_create(config: DtlnPipelineConfig | string): DtlnPipelineHandle;
+export declare function dtln_pipeline_destroy(handle: DtlnPipelineHandle): void;
+/** Any input length is accepted, output receives samples.length samples. */
+export declare function dtln_pipeline_process(
+  handle: DtlnPipelineHandle,
+  samples: Float32Array,
+  output: Float32Array,
+): void;
+/** Delay of the output in samples, at the pipeline sample rate. */
+export declare function dtln_pipeline_latency(handle: DtlnPipelineHandle): number;
+
+/** Node only. Denoise a whole 16khz recording on the libuv threadpool. */
+export declare const denoiseBuffer:
+  | ((samples: Float32Array, options?: DtlnOfflineOptions) => Promise<Float32Array>)
+  | undefined;
+/** Node only. Denoise a 16 bit mono wav file into a 32 bit float wav file. */
+export declare const denoiseFile:
+  | ((path: string, outPath: string, options?: DtlnOfflineOptions) => Promise<DtlnFileResult>)
+  | undefined;
+/** Node only. Alias of denoiseBuffer. */
+export declare const dtln_denoise_buffer: typeof denoiseBuffer | undefined;
+/** Node only. Alias of denoiseFile. */
+export declare const dtln_denoise_file: typeof denoiseFile | undefined;
+/** Node only. */
+export declare const DtlnTransform: typeof import("./dtln_stream").DtlnTransform | undefined;
+
+export interface DtlnBuffers {
+  input: Float32Array;
+  output: Float32Array;
+}
+
+/**
+ * WASM only. Resize the buffers of dtln_buffers to frameSize samples, e.g. 128
+ * for AudioWorklet render quanta, and return them.
+ */
+export declare const dtln_alloc_buffers: ((handle: DtlnHandle, frameSize: number) => DtlnBuffers) | undefined;
+/**
+ * WASM only. Views of the input and output buffers in the WASM heap. They are
+ * detached when the heap grows and replaced by dtln_alloc_buffers, so fetch
+ * them again in that case.
+ */
+export declare const dtln_buffers: ((handle: DtlnHandle) => DtlnBuffers) | undefined;
+/**
+ * WASM only. Denoise the first sampleCount samples of the input buffer, all of
+ * them by default, into the output buffer.
+ */
+export declare const dtln_denoise_buffers: ((handle: DtlnHandle, sampleCount?: number) => boolean) | undefined;
+
+/** WASM only. Callbacks run once the module has loaded. */
+export declare let postRun: Array<() => void> | undefined;
@@ -1,5 +1,17 @@
-if (process.platform == "darwin") {
-  module.exports = require("./index.node");
+if (process.platform == "darwin" || process.platform == "linux") {
+  const native = require("./index.node");
+  module.exports = {
+    ...native,
+    // Pipeline configs are objects or their JSON, as for WASM.
+    dtln_pipeline_create: (config) =>
+      native.dtln_pipeline_create(typeof config === "string" ? config : JSON.stringify(config)),
+    // Pipelines are freed by the garbage collector.
+    dtln_pipeline_destroy: () => {},
+    // Promise based whole buffer and file denoising on the libuv threadpool.
+    denoiseBuffer: native.dtln_denoise_buffer,
+    denoiseFile: native.dtln_denoise_file,
+    DtlnTransform: require("./dtln_stream.js").DtlnTransform,
+  };
 } else {
   module.exports = {};
 }
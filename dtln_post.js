@@ -1,20 +1,150 @@
-const DTLN_SAMPLE_BLOCK_SIZE = 512;
 const DTLN_SIZEOF_FLOAT32 = 4;
 
-// Export interface that matches the node plugin.
+// Sample rate of each handle, for the realtime factor in dtln_stats.
+const sampleRates = new Map();
+
+// Export interface that matches the node plugin, see dtln.d.ts.
 let DtlnPlugin = {
-  dtln_create: () => {
-    console.log(`Creating new DTLN ${Module}`);
-    return Module._dtln_create_wasm();
+  // options: any of sampleRate, native and minGainDb. Processing is always
+  // immediate, so mode and threads are ignored.
+  dtln_create: (options) => {
+    let sampleRate = options?.sampleRate ?? 16000;
+    let handle = Module._dtln_create_wasm(sampleRate, !!options?.native, options?.minGainDb ?? NaN);
+    if (handle < 0) {
+      throw new Error("Failed to create DTLN");
+    }
+    sampleRates.set(handle, sampleRate);
+    return handle;
+  },
+  dtln_destroy: (handle) => {
+    sampleRates.delete(handle);
+    Module._dtln_destroy_wasm(handle);
   },
-  dtln_destroy: (handle) => Module._dtln_destroy_wasm(handle),
+  dtln_stop: (handle) => DtlnPlugin.dtln_destroy(handle),
+  dtln_reset: (handle) => Module._dtln_reset_wasm(handle),
+  dtln_latency: (handle) => Module._dtln_latency_wasm(handle),
+  dtln_stats: (handle, reset) => {
+    let samples = Module._dtln_get_samples_wasm(handle);
+    let processingTimeMs = Module._dtln_get_processing_time_wasm(handle);
+    let audioMs = (samples / sampleRates.get(handle)) * 1000;
+    let stats = {
+      calls: Module._dtln_get_calls_wasm(handle),
+      samples,
+      // Immediate processing is never starved.
+      starvedCalls: 0,
+      processingTimeMs,
+      maxProcessingTimeMs: Module._dtln_get_max_processing_time_wasm(handle),
+      // Below 1.0 when faster than real time.
+      realtimeFactor: audioMs > 0 ? processingTimeMs / audioMs : 0,
+    };
+    if (reset) {
+      Module._dtln_reset_stats_wasm(handle);
+    }
+    return stats;
+  },
+  // Any input length is accepted, output receives input.length samples.
   dtln_denoise: (handle, input, output) => {
-    let audioBufferPtr = Module._dtln_get_audio_buffer(handle) / DTLN_SIZEOF_FLOAT32;
-    Module.HEAPF32.set(input, audioBufferPtr);
-    Module._dtln_denoise_wasm(handle);
-    output.set(Module.HEAPF32.subarray(audioBufferPtr, audioBufferPtr + DTLN_SAMPLE_BLOCK_SIZE));
-    return false;
+    if (input.length > Module._dtln_frame_size_wasm(handle)) {
+      Module._dtln_set_frame_size_wasm(handle, input.length);
+    }
+    let { input: inputView, output: outputView } = DtlnPlugin.dtln_buffers(handle);
+    inputView.set(input);
+    let starved = !!Module._dtln_denoise_wasm(handle, input.length);
+    output.set(outputView.subarray(0, input.length));
+    return starved;
+  },
+  // Resize the buffers of dtln_buffers to frameSize samples, e.g. 128 for
+  // AudioWorklet render quanta, and return them.
+  dtln_alloc_buffers: (handle, frameSize) => {
+    Module._dtln_set_frame_size_wasm(handle, frameSize);
+    return DtlnPlugin.dtln_buffers(handle);
+  },
+  // Views of the input and output buffers in the WASM heap, to denoise
+  // without copying. They are detached when the heap grows, and replaced by
+  // dtln_alloc_buffers, so fetch them again in that case.
+  dtln_buffers: (handle) => {
+    let frameSize = Module._dtln_frame_size_wasm(handle);
+    let inputPtr = Module._dtln_get_input_buffer(handle) / DTLN_SIZEOF_FLOAT32;
+    let outputPtr = Module._dtln_get_output_buffer(handle) / DTLN_SIZEOF_FLOAT32;
+    return {
+      input: Module.HEAPF32.subarray(inputPtr, inputPtr + frameSize),
+      output: Module.HEAPF32.subarray(outputPtr, outputPtr + frameSize),
+    };
+  },
+  // Denoise the first sampleCount samples of the input buffer, all of them by
+  // default, into the output buffer.
+  dtln_denoise_buffers: (handle, sampleCount) =>
+    !!Module._dtln_denoise_wasm(handle, sampleCount ?? Module._dtln_frame_size_wasm(handle)),
+  dtln_set_min_gain_db: (handle, minGainDb) => Module._dtln_set_min_gain_db_wasm(handle, minGainDb),
+  dtln_set_mix: (handle, wet) => Module._dtln_set_mix_wasm(handle, wet),
+  dtln_vad: (handle) => ({
+    speechProbability: Module._dtln_get_speech_probability_wasm(handle),
+    isSpeech: !!Module._dtln_is_speech_wasm(handle),
+  }),
+  // options: null to disable, or any of targetLevelDb, maxGainDb, attackMs,
+  // releaseMs and vadGated.
+  dtln_set_agc: (handle, options) =>
+    Module._dtln_set_agc_wasm(
+      handle,
+      !!options,
+      options?.targetLevelDb ?? NaN,
+      options?.maxGainDb ?? NaN,
+      options?.attackMs ?? NaN,
+      options?.releaseMs ?? NaN,
+      options?.vadGated ?? true,
+    ),
+  // options: null to disable, or any of ceilingDb, lookaheadMs and releaseMs.
+  dtln_set_limiter: (handle, options) =>
+    Module._dtln_set_limiter_wasm(
+      handle,
+      !!options,
+      options?.ceilingDb ?? NaN,
+      options?.lookaheadMs ?? NaN,
+      options?.releaseMs ?? NaN,
+    ),
+  // options: any of dcBlocker and highPassHz, omitted filters are disabled.
+  dtln_set_pre_filter: (handle, options) =>
+    Module._dtln_set_pre_filter_wasm(
+      handle,
+      !!options?.dcBlocker,
+      options?.highPassHz ?? NaN,
+    ),
+  dtln_clip_stats: (handle, reset) => {
+    let stats = {
+      clippedSamples: Module._dtln_get_clipped_samples_wasm(handle),
+      limitedSamples: Module._dtln_get_limited_samples_wasm(handle),
+    };
+    if (reset) {
+      Module._dtln_reset_clip_stats_wasm(handle);
+    }
+    return stats;
+  },
+  // config: a pipeline config object or its JSON, see audio_pipeline.rs.
+  dtln_pipeline_create: (config) => {
+    let json = typeof config === "string" ? config : JSON.stringify(config);
+    let bytes = new TextEncoder().encode(json);
+    let ptr = Module._dtln_alloc_wasm(bytes.length);
+    Module.HEAPU8.set(bytes, ptr);
+    let handle = Module._dtln_pipeline_create_wasm(ptr, bytes.length);
+    Module._dtln_free_wasm(ptr, bytes.length);
+    if (handle < 0) {
+      throw new Error("Failed to create DTLN pipeline");
+    }
+    return handle;
+  },
+  dtln_pipeline_destroy: (handle) => Module._dtln_pipeline_destroy_wasm(handle),
+  // Any input length is accepted, output receives input.length samples.
+  dtln_pipeline_process: (handle, input, output) => {
+    // The buffer grows to fit input, which may grow the heap, so HEAPF32 is
+    // only read afterwards.
+    let bufferPtr = Module._dtln_pipeline_get_buffer_wasm(handle, input.length) / DTLN_SIZEOF_FLOAT32;
+    Module.HEAPF32.set(input, bufferPtr);
+    if (!Module._dtln_pipeline_process_wasm(handle, input.length)) {
+      throw new Error("Failed to process DTLN pipeline");
+    }
+    output.set(Module.HEAPF32.subarray(bufferPtr, bufferPtr + input.length));
   },
+  dtln_pipeline_latency: (handle) => Module._dtln_pipeline_latency_wasm(handle),
 };
 
 if (typeof module !== "undefined") {
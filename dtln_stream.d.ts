@@ -0,0 +1,30 @@
+import { Transform, TransformOptions } from "stream";
+import { DtlnStats } from "./dtln";
+
+export { DtlnStats };
+
+export interface DtlnTransformOptions extends TransformOptions {
+  /** Mono PCM sample format of the input and output, "s16le" by default. */
+  format?: "s16le" | "f32le";
+  /** Sample rate of the PCM, 16000 by default. Other rates are resampled. */
+  sampleRate?: number;
+  /** "immediate" (the default) or "deferred" processing, see dtln_create. */
+  mode?: "immediate" | "deferred";
+  /** TFLite interpreter threads. */
+  threads?: number;
+  /** Use the native model implementation instead of TFLite. */
+  native?: boolean;
+  /** Suppression level, the minimum gain of the suppression mask. */
+  minGainDb?: number;
+}
+
+/**
+ * Denoises raw PCM. Accepts chunks of any size, and outputs the same number
+ * of samples, time aligned with the input.
+ */
+export declare class DtlnTransform extends Transform {
+  constructor(options?: DtlnTransformOptions);
+  /** Samples the output is delayed by inside the denoiser. */
+  readonly latency: number;
+  stats(reset?: boolean): DtlnStats;
+}
@@ -0,0 +1,172 @@
+// Transform stream over the native module, for piping raw PCM from ffmpeg,
+// WebSockets and similar sources through the denoiser.
+//
+// Chunks of any size are buffered into whole blocks before they are denoised.
+// The output is time aligned with the input and has the same length: the
+// leading latency is dropped, and the tail is pushed out with silence when the
+// stream ends.
+
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const { Transform } = require("stream");
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const native = require("./index.node");
+
+// Samples per dtln_denoise call at 16khz, a multiple of the 128 sample block.
+const BLOCK_SIZE = 1024;
+
+// 16 bit samples are scaled like the wav files read by the command line tool,
+// so both produce the same output.
+const S16_SCALE = 65535;
+
+const FORMATS = {
+  s16le: {
+    bytes: 2,
+    read: (buffer, offset) => buffer.readInt16LE(offset) / S16_SCALE,
+    write: (buffer, sample, offset) =>
+      buffer.writeInt16LE(Math.max(-32768, Math.min(32767, Math.round(sample * S16_SCALE))), offset),
+  },
+  f32le: {
+    bytes: 4,
+    read: (buffer, offset) => buffer.readFloatLE(offset),
+    write: (buffer, sample, offset) => buffer.writeFloatLE(sample, offset),
+  },
+};
+
+class DtlnTransform extends Transform {
+  /**
+   * @param {object} options - Any of format ("s16le" or "f32le", mono,
+   * "s16le" by default), the dtln_create options sampleRate, mode
+   * ("immediate" by default), threads, native and minGainDb, and the options
+   * of stream.Transform.
+   */
+  constructor(options = {}) {
+    const {
+      format = "s16le",
+      sampleRate = 16000,
+      mode = "immediate",
+      threads,
+      native: useNative,
+      minGainDb,
+      ...streamOptions
+    } = options;
+    super(streamOptions);
+
+    this._format = FORMATS[format];
+    if (!this._format) {
+      throw new Error(`Unsupported format ${format}, expected one of ${Object.keys(FORMATS)}`);
+    }
+    this._denoiser = native.dtln_create({
+      mode,
+      sampleRate,
+      threads,
+      native: useNative,
+      minGainDb,
+    });
+    this._stopped = false;
+
+    this._blockSize = Math.round((BLOCK_SIZE * sampleRate) / 16000);
+    // Bytes waiting for a whole block.
+    this._pending = Buffer.alloc(0);
+    // Deferred processing returns the previous block, one block later.
+    this._skip = native.dtln_latency(this._denoiser) + (mode === "deferred" ? this._blockSize : 0);
+    this._inputSamples = 0;
+    this._outputSamples = 0;
+  }
+
+  /**
+   * Samples the output is delayed by inside the denoiser, which the stream
+   * compensates for.
+   */
+  get latency() {
+    return native.dtln_latency(this._denoiser);
+  }
+
+  /**
+   * Processing statistics, see dtln_stats.
+   */
+  stats(reset = false) {
+    return native.dtln_stats(this._denoiser, reset);
+  }
+
+  _transform(chunk, encoding, callback) {
+    try {
+      const pending = Buffer.concat([this._pending, chunk]);
+      const blockBytes = this._blockSize * this._format.bytes;
+      const whole = pending.length - (pending.length % blockBytes);
+      for (let offset = 0; offset < whole; offset += blockBytes) {
+        const block = this._decode(pending.subarray(offset, offset + blockBytes));
+        this._inputSamples += block.length;
+        this._denoise(block);
+      }
+      this._pending = pending.subarray(whole);
+      callback();
+    } catch (e) {
+      callback(e);
+    }
+  }
+
+  _flush(callback) {
+    try {
+      // A trailing partial sample is dropped.
+      const bytes = this._format.bytes;
+      const tail = this._decode(this._pending.subarray(0, this._pending.length - (this._pending.length % bytes)));
+      this._inputSamples += tail.length;
+      this._pending = Buffer.alloc(0);
+
+      const block = new Float32Array(this._blockSize);
+      block.set(tail);
+      this._denoise(block);
+
+      // Push the remaining input through the latency with silence.
+      let blocks = Math.ceil(this._skip / this._blockSize) + 2;
+      while (this._outputSamples < this._inputSamples && blocks-- > 0) {
+        this._denoise(new Float32Array(this._blockSize));
+      }
+      callback();
+    } catch (e) {
+      callback(e);
+    }
+  }
+
+  _destroy(error, callback) {
+    if (!this._stopped) {
+      this._stopped = true;
+      native.dtln_stop(this._denoiser);
+    }
+    callback(error);
+  }
+
+  _denoise(block) {
+    let output = new Float32Array(block.length);
+    native.dtln_denoise(this._denoiser, block, output);
+
+    if (this._skip > 0) {
+      const skipped = Math.min(this._skip, output.length);
+      output = output.subarray(skipped);
+      this._skip -= skipped;
+    }
+    output = output.subarray(0, Math.max(0, this._inputSamples - this._outputSamples));
+    if (output.length > 0) {
+      this._outputSamples += output.length;
+      this.push(this._encode(output));
+    }
+  }
+
+  _decode(buffer) {
+    const samples = new Float32Array(buffer.length / this._format.bytes);
+    for (let i = 0; i < samples.length; i++) {
+      samples[i] = this._format.read(buffer, i * this._format.bytes);
+    }
+    return samples;
+  }
+
+  _encode(samples) {
+    const buffer = Buffer.alloc(samples.length * this._format.bytes);
+    for (let i = 0; i < samples.length; i++) {
+      this._format.write(buffer, samples[i], i * this._format.bytes);
+    }
+    return buffer;
+  }
+}
+
+module.exports = { DtlnTransform };
@@ -0,0 +1,143 @@
+// AudioWorkletProcessor denoising the first input channel with the WASM build.
+//
+// install-wasm.js appends this file to the WASM module as dtln_worklet.js, so
+// a single audioWorklet.addModule() call loads both:
+//
+//   await context.audioWorklet.addModule("dtln_worklet.js");
+//   const node = new AudioWorkletNode(context, "dtln", {
+//     processorOptions: { native: true },
+//   });
+//   node.parameters.get("suppressionDb").value = -20;
+//
+// Render quanta are passed through the WASM buffers without copies, and the
+// context sample rate is resampled to 16khz and back by the processor. Until
+// the module has loaded, the input is passed through unchanged.
+
+// Suppression levels at or below this apply the mask at full strength.
+const DTLN_FULL_SUPPRESSION_DB = -100;
+
+// Set once the WASM module has loaded, see DtlnPlugin.postRun in dtln_post.js.
+let dtlnLoaded = false;
+DtlnPlugin.postRun = DtlnPlugin.postRun || [];
+DtlnPlugin.postRun.push(() => {
+  dtlnLoaded = true;
+});
+
+class DtlnWorkletProcessor extends AudioWorkletProcessor {
+  static get parameterDescriptors() {
+    return [
+      // 0 passes the input through, time aligned with the denoised output.
+      { name: "enabled", defaultValue: 1, minValue: 0, maxValue: 1, automationRate: "k-rate" },
+      // Minimum gain of the suppression mask in dB.
+      {
+        name: "suppressionDb",
+        defaultValue: DTLN_FULL_SUPPRESSION_DB,
+        minValue: DTLN_FULL_SUPPRESSION_DB,
+        maxValue: 0,
+        automationRate: "k-rate",
+      },
+    ];
+  }
+
+  /**
+   * @param {object} options - processorOptions may set native, to use the
+   * native model implementation instead of TFLite.
+   */
+  constructor(options) {
+    super();
+    this._native = !!options?.processorOptions?.native;
+    this._handle = null;
+    this._buffers = null;
+    this._enabled = null;
+    this._suppressionDb = null;
+    this._destroyed = false;
+
+    // {type: "stats", reset} replies with {type: "stats", stats}, and
+    // {type: "destroy"} frees the denoiser and ends processing.
+    this.port.onmessage = (event) => {
+      switch (event.data?.type) {
+        case "stats":
+          this.port.postMessage({
+            type: "stats",
+            stats: this._handle === null ? null : DtlnPlugin.dtln_stats(this._handle, !!event.data.reset),
+          });
+          break;
+        case "destroy":
+          this._destroy();
+          break;
+      }
+    };
+  }
+
+  process(inputs, outputs, parameters) {
+    if (this._destroyed) {
+      return false;
+    }
+    const input = inputs[0]?.[0];
+    const output = outputs[0];
+    if (!output || output.length === 0) {
+      return true;
+    }
+    const frameSize = output[0].length;
+
+    if (this._handle === null && dtlnLoaded) {
+      this._create();
+    }
+    if (this._handle === null) {
+      for (const channel of output) {
+        input ? channel.set(input) : channel.fill(0);
+      }
+      return true;
+    }
+
+    this._applyParameters(parameters);
+
+    // The views are detached when the WASM heap grows.
+    if (this._buffers === null || this._buffers.input.length !== frameSize) {
+      this._buffers = DtlnPlugin.dtln_alloc_buffers(this._handle, frameSize);
+    } else if (this._buffers.input.length === 0) {
+      this._buffers = DtlnPlugin.dtln_buffers(this._handle);
+    }
+
+    // Disconnected inputs have no channels, and are silent.
+    input ? this._buffers.input.set(input) : this._buffers.input.fill(0);
+    DtlnPlugin.dtln_denoise_buffers(this._handle, frameSize);
+    for (const channel of output) {
+      channel.set(this._buffers.output);
+    }
+    return true;
+  }
+
+  _create() {
+    // sampleRate is a global of the AudioWorkletGlobalScope.
+    this._handle = DtlnPlugin.dtln_create({ sampleRate, native: this._native });
+    this.port.postMessage({ type: "ready", latency: DtlnPlugin.dtln_latency(this._handle) });
+  }
+
+  _applyParameters(parameters) {
+    const enabled = parameters.enabled[0] >= 0.5;
+    if (enabled !== this._enabled) {
+      this._enabled = enabled;
+      DtlnPlugin.dtln_set_mix(this._handle, enabled ? 1 : 0);
+    }
+    const suppressionDb = parameters.suppressionDb[0];
+    if (suppressionDb !== this._suppressionDb) {
+      this._suppressionDb = suppressionDb;
+      DtlnPlugin.dtln_set_min_gain_db(
+        this._handle,
+        suppressionDb <= DTLN_FULL_SUPPRESSION_DB ? -Infinity : suppressionDb
+      );
+    }
+  }
+
+  _destroy() {
+    if (this._handle !== null) {
+      DtlnPlugin.dtln_destroy(this._handle);
+      this._handle = null;
+    }
+    this._buffers = null;
+    this._destroyed = true;
+  }
+}
+
+registerProcessor("dtln", DtlnWorkletProcessor);
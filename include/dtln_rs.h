@@ -0,0 +1,138 @@
+/* C API of dtln-rs. Generated by cbindgen from src/dtln_capi.rs, do not edit. */
+
+#ifndef DTLN_RS_H
+#define DTLN_RS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define DTLN_ABI_VERSION 2
+
+#define DTLN_BLOCK_LEN 512
+
+#define DTLN_BLOCK_SHIFT 128
+
+#define DTLN_FFT_OUT_SIZE ((DTLN_BLOCK_LEN / 2) + 1)
+
+typedef enum {
+  DTLN_STATUS_OK = 0,
+  DTLN_STATUS_NULL_POINTER = 1,
+  DTLN_STATUS_INVALID_ARGUMENT = 2,
+  DTLN_STATUS_CREATE_FAILED = 3,
+  DTLN_STATUS_INTERNAL = 4,
+} DtlnStatus;
+
+typedef struct DtlnEngine DtlnEngine;
+
+typedef struct {
+  float target_level_db;
+  float max_gain_db;
+  float attack_ms;
+  float release_ms;
+  bool vad_gated;
+} DtlnAgcConfig;
+
+typedef struct {
+  float ceiling_db;
+  float lookahead_ms;
+  float release_ms;
+} DtlnLimiterConfig;
+
+typedef struct {
+  size_t struct_size;
+  bool native;
+  float min_gain_db;
+  float mix;
+  bool dc_blocker;
+  float high_pass_hz;
+  bool agc_enabled;
+  DtlnAgcConfig agc;
+  bool limiter_enabled;
+  DtlnLimiterConfig limiter;
+} DtlnConfig;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+uint32_t dtln_abi_version(void);
+
+/**
+ * Fill config with the default settings, which match a plain DtlnEngine.
+ * config->struct_size must be set to sizeof(DtlnConfig) first.
+ *
+ * # Safety
+ * config must be null or point to a writable DtlnConfig.
+ */
+DtlnStatus dtln_config_default(DtlnConfig *config);
+
+/**
+ * Create an engine. On success *engine receives it, to be released with
+ * dtln_engine_free().
+ * @param config Settings of the engine, null for the defaults. A config with
+ * the wrong struct_size, or a NaN min_gain_db or mix, is rejected with
+ * DTLN_STATUS_INVALID_ARGUMENT.
+ *
+ * # Safety
+ * config must be null or point to a DtlnConfig, engine must be null or
+ * writable.
+ */
+DtlnStatus dtln_engine_new(const DtlnConfig *config, DtlnEngine **engine);
+
+/**
+ * Change the settings of an engine. Stages keep their state where possible,
+ * except that changing the native flag has no effect. Configs are checked as
+ * by dtln_engine_new().
+ *
+ * # Safety
+ * engine must be null or come from dtln_engine_new(), config must be null or
+ * point to a DtlnConfig.
+ */
+DtlnStatus dtln_engine_configure(DtlnEngine *engine, const DtlnConfig *config);
+
+/**
+ * Denoise 16khz mono samples. The output is delayed by
+ * dtln_engine_latency() samples.
+ * @param sample_count Length of input and output, a multiple of
+ * DTLN_BLOCK_SHIFT. input and output may be the same buffer.
+ *
+ * # Safety
+ * engine must be null or come from dtln_engine_new(). input and output must
+ * be null or hold sample_count floats.
+ */
+DtlnStatus dtln_engine_process(DtlnEngine *engine,
+                               const float *input,
+                               float *output,
+                               size_t sample_count);
+
+/**
+ * Delay between an input sample and the matching output sample, 0 for a null
+ * engine.
+ *
+ * # Safety
+ * engine must be null or come from dtln_engine_new().
+ */
+size_t dtln_engine_latency(const DtlnEngine *engine);
+
+/**
+ * Release an engine created by dtln_engine_new(). Null is ignored.
+ *
+ * # Safety
+ * engine must not be used afterwards.
+ */
+void dtln_engine_free(DtlnEngine *engine);
+
+/**
+ * A static, human readable description of a DtlnStatus value. Values which
+ * are not a DtlnStatus give "unknown status".
+ */
+const char *dtln_status_string(uint32_t status);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* DTLN_RS_H */
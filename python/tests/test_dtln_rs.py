@@ -0,0 +1,134 @@
+# Tests of the Python bindings. Build them into the current environment first:
+#
+#   pip install maturin numpy pytest
+#   maturin develop --release
+#   pytest
+
+import threading
+
+import numpy as np
+import pytest
+
+import dtln_rs
+
+
+def noisy_tone(seconds=1.0, rate=dtln_rs.SAMPLE_RATE, seed=0):
+    rng = np.random.default_rng(seed)
+    t = np.arange(int(seconds * rate)) / rate
+    tone = 0.2 * np.sin(2 * np.pi * 440 * t)
+    return (tone + 0.05 * rng.standard_normal(t.size)).astype(np.float32)
+
+
+def test_streaming_matches_single_call():
+    samples = noisy_tone()
+
+    whole = dtln_rs.DtlnEngine(native=True).process(samples)
+    assert whole.dtype == np.float32
+    assert whole.size == samples.size // dtln_rs.BLOCK_SHIFT * dtln_rs.BLOCK_SHIFT
+
+    engine = dtln_rs.DtlnEngine(native=True)
+    chunks = np.array_split(samples, [1, 300, 301, 4000, 9000])
+    streamed = np.concatenate([engine.process(chunk) for chunk in chunks])
+    np.testing.assert_allclose(streamed, whole, atol=1e-6)
+
+
+def test_denoise_whole_file():
+    samples = noisy_tone(seconds=2.0)
+    output = dtln_rs.denoise(samples, native=True)
+
+    assert output.shape == samples.shape
+    assert np.all(np.isfinite(output))
+    # The white noise is reduced, so less energy remains.
+    assert np.sum(output**2) < np.sum(samples**2)
+
+    segmented = dtln_rs.denoise(samples, native=True, segment_seconds=0.5, threads=2)
+    assert segmented.shape == samples.shape
+
+
+def test_engine_settings():
+    engine = dtln_rs.DtlnEngine(native=True)
+    engine.set_min_gain_db(-12.0)
+    engine.set_mix(0.5)
+    engine.set_pre_filter(dc_blocker=True, high_pass_hz=80.0)
+    engine.set_agc({"target_level_db": -20.0, "vad_gated": False})
+    engine.set_agc(None)
+    with pytest.raises(ValueError):
+        engine.set_agc({"target_level_db": "loud"})
+    engine.set_limiter({"ceiling_db": -6.0, "lookahead_ms": 5.0})
+    assert engine.latency == 384 + 80
+
+    output = engine.process(noisy_tone() * 4)
+    assert np.max(np.abs(output)) <= 10 ** (-6.0 / 20) + 1e-6
+
+    vad = engine.vad()
+    assert 0.0 <= vad["speech_probability"] <= 1.0
+    assert isinstance(vad["is_speech"], bool)
+
+    stats = engine.clip_stats(reset=True)
+    assert stats["limited_samples"] > 0
+    assert engine.clip_stats()["limited_samples"] == 0
+
+    engine.set_limiter(None)
+    assert engine.latency == 384
+
+
+def test_pipeline_is_delayed_by_latency():
+    rate = 48000
+    pipeline = dtln_rs.Pipeline(
+        {"sample_rate": rate, "stages": [{"type": "gain", "db": 0.0}]}
+    )
+    assert pipeline.sample_rate == rate
+
+    samples = noisy_tone(rate=rate)
+    output = np.concatenate(
+        [pipeline.process(chunk) for chunk in np.array_split(samples, 37)]
+    )
+    assert output.shape == samples.shape
+
+    latency = pipeline.latency
+    # Compare below the resampler cutoff, as the noise extends up to 24khz.
+    tone = np.sin(2 * np.pi * 440 * np.arange(samples.size) / rate)
+    correlation = np.dot(output[latency:], tone[:-latency]) / np.dot(
+        samples[:-latency], tone[:-latency]
+    )
+    assert abs(correlation - 1.0) < 0.01
+
+
+def test_invalid_arguments():
+    with pytest.raises(ValueError):
+        dtln_rs.Pipeline("{not json")
+    with pytest.raises(RuntimeError):
+        dtln_rs.Pipeline({"stages": [{"type": "agc"}]})
+    with pytest.raises(TypeError):
+        dtln_rs.DtlnEngine(native=True).process(np.zeros(512, dtype=np.float64))
+
+
+def test_processors():
+    samples = noisy_tone()[:1024]
+
+    immediate = dtln_rs.ImmediateProcessor()
+    assert immediate.denoise(samples).shape == samples.shape
+
+    deferred = dtln_rs.DeferredProcessor()
+    # The first call returns silence while the block is being processed.
+    first, _ = deferred.denoise(samples)
+    assert not np.any(first)
+    deferred.stop()
+
+
+def test_engines_run_in_parallel_threads():
+    samples = noisy_tone()
+    expected = dtln_rs.DtlnEngine(native=True).process(samples)
+    results = [None] * 4
+
+    def run(index):
+        results[index] = dtln_rs.DtlnEngine(native=True).process(samples)
+
+    threads = [threading.Thread(target=run, args=(i,)) for i in range(len(results))]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+
+    for result in results:
+        np.testing.assert_allclose(result, expected, atol=1e-6)
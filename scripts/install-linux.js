@@ -0,0 +1,84 @@
+// Compile and copy the native Node module on Linux x86_64 and aarch64.
+//
+// tensorflowlite_c is found by build.rs (TFLITE_LIB_DIR, pkg-config,
+// TFLITE_SOURCE_DIR or tflite_lib/). When a prebuilt library is linked
+// dynamically, it is copied next to index.node, which build.rs makes the
+// module look in first.
+
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const { execSync } = require("child_process");
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const { copyFileSync, existsSync } = require("fs");
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const { join } = require("path");
+
+if (process.platform !== "linux") {
+  console.error("install-linux only builds on Linux, use install-native instead.");
+  process.exit(1);
+}
+
+// TARGET_ARCH selects the architecture like for the macOS builds, defaulting
+// to the one Node runs on.
+const targets = {
+  x64: "x86_64-unknown-linux-gnu",
+  arm64: "aarch64-unknown-linux-gnu",
+};
+const arch = process.env.TARGET_ARCH || process.arch;
+const target = targets[arch];
+if (!target) {
+  console.error(`Unsupported architecture ${arch}, expected one of ${Object.keys(targets)}.`);
+  process.exit(1);
+}
+
+let targetInstalled = false;
+try {
+  const output = execSync("rustup target list --installed").toString();
+  targetInstalled = output.includes(target);
+} catch (e) {
+  console.error(`Unable to determine if ${target} target is installed.`);
+  process.exit(1);
+}
+
+if (!targetInstalled) {
+  try {
+    execSync(`rustup target add ${target}`, { stdio: "inherit" });
+  } catch (e) {
+    console.error(e);
+    process.exit(1);
+  }
+}
+
+// Cross compiling needs a linker for the target, default to the GNU cross
+// toolchain unless one is configured.
+const env = { ...process.env };
+const linkerVariable = `CARGO_TARGET_${target.toUpperCase().replace(/-/g, "_")}_LINKER`;
+if (arch !== process.arch && !env[linkerVariable]) {
+  env[linkerVariable] = `${target.split("-")[0]}-linux-gnu-gcc`;
+}
+
+try {
+  execSync(
+    "npx cargo-cp-artifact -a cdylib dtln-rs index.node -- " +
+      `cargo build -p dtln-rs --lib --release --message-format=json-render-diagnostics --target ${target}`,
+    { stdio: "inherit", env }
+  );
+} catch (e) {
+  console.error(e);
+  process.exit(1);
+}
+
+// Bundle a dynamically linked prebuilt tensorflowlite_c.
+const linkedStatically = env.TFLITE_STATIC && env.TFLITE_STATIC !== "0";
+if (!linkedStatically) {
+  const libDirs = [env.TFLITE_LIB_DIR, join(__dirname, "..", "tflite_lib")].filter(Boolean);
+  const library = libDirs
+    .map((dir) => join(dir, "libtensorflowlite_c.so"))
+    .find((path) => existsSync(path));
+  if (library) {
+    const libraryDest = join(__dirname, "..", "libtensorflowlite_c.so");
+    console.log(" " + library + " -> " + libraryDest);
+    copyFileSync(library, libraryDest);
+  }
+}
+
+copyFileSync(join(__dirname, "..", "dtln.node.js"), join(__dirname, "..", "dtln.js"));
@@ -95,3 +95,15 @@ try {
   console.error(err);
   process.exit(1);
 }
+
+// The worklet loads as one module, so the processor is appended to the build.
+const worklet = join(__dirname, "..", "dtln_worklet_processor.js");
+const workletDest = join(__dirname, "..", "dtln_worklet.js");
+console.log(" " + js + " + " + worklet + " -> " + workletDest);
+
+try {
+  writeFileSync(workletDest, readFileSync(js, "utf8") + "\n" + readFileSync(worklet, "utf8"));
+} catch (err) {
+  console.error(err);
+  process.exit(1);
+}
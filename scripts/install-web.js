@@ -0,0 +1,61 @@
+// Build the wasm-bindgen ES module for browsers and Deno into pkg/. Unlike
+// install-wasm.js this needs no emscripten, inference uses the native Rust
+// implementation with wasm SIMD.
+
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const { execSync } = require("child_process");
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+let { join } = require("path");
+
+const target = "wasm32-unknown-unknown";
+
+let targetInstalled = false;
+try {
+  const output = execSync("rustup target list --installed").toString();
+  targetInstalled = output.includes(target);
+} catch (e) {
+  console.error(`Unable to determine if ${target} target is installed.`);
+  process.exit(1);
+}
+
+if (!targetInstalled) {
+  try {
+    execSync(`rustup target add ${target}`, { stdio: "inherit" });
+  } catch (e) {
+    console.error(e);
+    process.exit(1);
+  }
+}
+
+try {
+  execSync("wasm-bindgen --version");
+} catch (e) {
+  // The CLI version has to match the wasm-bindgen crate in Cargo.lock.
+  console.error("wasm-bindgen is not installed. Install it with: cargo install wasm-bindgen-cli");
+  process.exit(1);
+}
+
+// SIMD is enabled by the rustflags for this target in .cargo/config.toml.
+try {
+  execSync(`cargo build --lib --release --message-format=json-render-diagnostics --target ${target}`, {
+    stdio: "inherit",
+  });
+} catch (e) {
+  console.error(e);
+  process.exit(1);
+}
+
+const wasm = join(__dirname, "..", "target", target, "release", "dtln_rs.wasm");
+const outDir = join(__dirname, "..", "pkg");
+
+console.log("Generating bindings...");
+console.log(" " + wasm + " -> " + join(outDir, "dtln.js"));
+
+try {
+  execSync(`wasm-bindgen --target web --out-dir "${outDir}" --out-name dtln "${wasm}"`, {
+    stdio: "inherit",
+  });
+} catch (e) {
+  console.error(e);
+  process.exit(1);
+}
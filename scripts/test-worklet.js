@@ -0,0 +1,95 @@
+// Run the AudioWorkletProcessor in dtln_worklet.js headlessly in Node, after
+// npm run install-wasm. The globals of the AudioWorkletGlobalScope are stubbed,
+// and render quanta are fed to the processor as the audio thread would.
+
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const assert = require("assert");
+// eslint-disable-next-line @typescript-eslint/no-var-requires
+const { join } = require("path");
+
+const SAMPLE_RATE = 48000;
+const QUANTUM = 128;
+
+const messages = [];
+let Processor = null;
+
+global.sampleRate = SAMPLE_RATE;
+global.AudioWorkletProcessor = class {
+  constructor() {
+    this.port = {
+      onmessage: null,
+      postMessage: (data) => messages.push(data),
+    };
+  }
+};
+global.registerProcessor = (name, processorClass) => {
+  assert.strictEqual(name, "dtln");
+  Processor = processorClass;
+};
+
+require(join(__dirname, "..", "dtln_worklet.js"));
+assert(Processor, "dtln_worklet.js did not register a processor");
+
+const parameterDefaults = () =>
+  Object.fromEntries(Processor.parameterDescriptors.map((p) => [p.name, new Float32Array([p.defaultValue])]));
+
+// Feed input to the processor one quantum at a time, returning its output.
+function run(processor, input, parameters) {
+  const output = new Float32Array(input.length);
+  for (let offset = 0; offset + QUANTUM <= input.length; offset += QUANTUM) {
+    const outputs = [[new Float32Array(QUANTUM), new Float32Array(QUANTUM)]];
+    assert(processor.process([[input.subarray(offset, offset + QUANTUM)]], outputs, parameters));
+    assert.deepStrictEqual(outputs[0][0], outputs[0][1]);
+    output.set(outputs[0][0], offset);
+  }
+  return output;
+}
+
+const rms = (samples) => Math.sqrt(samples.reduce((sum, s) => sum + s * s, 0) / samples.length);
+
+async function main() {
+  const processor = new Processor({ processorOptions: { native: true } });
+
+  // The input passes through until the WASM module has loaded.
+  const silence = new Float32Array(QUANTUM);
+  for (let i = 0; i < 1000 && !messages.some((m) => m.type === "ready"); i++) {
+    run(processor, silence, parameterDefaults());
+    await new Promise((resolve) => setTimeout(resolve, 10));
+  }
+  const ready = messages.find((m) => m.type === "ready");
+  assert(ready, "The WASM module did not load");
+  console.log(`Loaded, latency ${ready.latency} samples`);
+
+  const seconds = 2;
+  const noise = Float32Array.from({ length: SAMPLE_RATE * seconds }, () => (Math.random() - 0.5) * 0.2);
+
+  const denoised = run(processor, noise, parameterDefaults());
+  assert(denoised.every(Number.isFinite));
+
+  const bypass = parameterDefaults();
+  bypass.enabled[0] = 0;
+  const passed = run(processor, noise, bypass);
+
+  // Skip the start, where the mix ramps and the latency is filled.
+  const tail = SAMPLE_RATE / 2;
+  const denoisedRms = rms(denoised.subarray(tail));
+  const passedRms = rms(passed.subarray(tail));
+  console.log(`Noise RMS ${rms(noise).toFixed(4)}, denoised ${denoisedRms.toFixed(4)}, bypassed ${passedRms.toFixed(4)}`);
+  assert(denoisedRms < passedRms / 2, "Noise was not suppressed");
+  assert(passedRms > rms(noise) / 2, "Bypassed audio was not passed through");
+
+  processor.port.onmessage({ data: { type: "stats", reset: true } });
+  const stats = messages.find((m) => m.type === "stats").stats;
+  // The quantum which loaded the module is denoised too.
+  assert.strictEqual(stats.samples, noise.length * 2 + QUANTUM);
+  console.log(`Realtime factor ${stats.realtimeFactor.toFixed(3)}`);
+
+  processor.port.onmessage({ data: { type: "destroy" } });
+  assert(!processor.process([[silence]], [[new Float32Array(QUANTUM)]], parameterDefaults()));
+  console.log("Worklet test passed");
+}
+
+main().catch((e) => {
+  console.error(e);
+  process.exit(1);
+});
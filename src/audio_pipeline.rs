@@ -0,0 +1,392 @@
+// Chain of processing stages around DtlnEngine, built from a JSON config.
+//
+// The pipeline runs at any sample rate. Input is resampled to 16khz, passed
+// through the stages in DTLN_BLOCK_SHIFT frames and resampled back, so callers
+// can push buffers of any length and get the same number of samples back.
+//
+// Example config:
+//
+//     {
+//       "sample_rate": 48000,
+//       "stages": [
+//         { "type": "high_pass", "cutoff_hz": 80 },
+//         { "type": "denoise", "min_gain_db": -20 },
+//         { "type": "agc", "target_level_db": -23 },
+//         { "type": "limiter", "ceiling_db": -1 }
+//       ]
+//     }
+
+use std::collections::VecDeque;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use crate::audio_stage::{AudioStage, StageChain};
+use crate::constants::DTLN_BLOCK_SHIFT;
+use crate::dtln_agc::AgcConfig;
+use crate::dtln_engine::DtlnEngine;
+use crate::dtln_filters::{Biquad, DcBlocker};
+use crate::dtln_limiter::{DtlnLimiter, LimiterConfig};
+use crate::resampler::Resampler;
+
+// DTLN sample rate.
+const SAMPLE_RATE: u32 = 16000;
+
+// Butterworth response for the high-pass stage.
+const HIGH_PASS_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    // Rate of the samples passed to and returned by the pipeline.
+    pub sample_rate: u32,
+    // Stages in processing order.
+    pub stages: Vec<StageConfig>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            sample_rate: SAMPLE_RATE,
+            stages: vec![StageConfig::Denoise {
+                native: false,
+                min_gain_db: None,
+                mix: None,
+            }],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StageConfig {
+    DcBlocker,
+    HighPass {
+        cutoff_hz: f32,
+    },
+    Gain {
+        db: f32,
+    },
+    Denoise {
+        // Use the native model implementation instead of TFLite.
+        #[serde(default)]
+        native: bool,
+        #[serde(default)]
+        min_gain_db: Option<f32>,
+        #[serde(default)]
+        mix: Option<f32>,
+    },
+    // Must directly follow a denoise stage, whose VAD gates it.
+    Agc(AgcConfig),
+    Limiter(LimiterConfig),
+}
+
+/**
+ * Fixed gain, e.g. to match the input level to what the model expects.
+ */
+pub struct GainStage {
+    gain: f32,
+}
+
+impl GainStage {
+    pub fn new(gain_db: f32) -> Self {
+        GainStage {
+            gain: 10f32.powf(gain_db / 20.0),
+        }
+    }
+}
+
+impl AudioStage for GainStage {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            *sample *= self.gain;
+        }
+    }
+}
+
+// The adapter hands the stages whole DTLN_BLOCK_SHIFT frames, so the engine
+// runs on them directly, without the buffering of its own AudioStage impl.
+struct EngineStage(DtlnEngine);
+
+impl AudioStage for EngineStage {
+    fn process(&mut self, samples: &mut [f32]) {
+        self.0.denoise_in_place(samples);
+    }
+
+    fn latency(&self) -> usize {
+        self.0.latency()
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+/**
+ * Runs 16khz processing at another sample rate. Input of any length is
+ * resampled to 16khz and handed on in whole DTLN_BLOCK_SHIFT frames, and the
+ * processed frames are resampled back, so every call returns as many samples
+ * as it is given.
+ */
+pub struct FrameAdapter {
+    sample_rate: u32,
+    // Conversion to and from 16khz, None when running at 16khz.
+    input_resampler: Option<Resampler>,
+    output_resampler: Option<Resampler>,
+    // 16khz samples waiting for a whole DTLN_BLOCK_SHIFT frame.
+    pending: Vec<f32>,
+    // Processed samples at the adapter rate, primed with silence so output
+    // is available while the first frame fills.
+    output: VecDeque<f32>,
+    // Silence the output queue was primed with.
+    priming: usize,
+}
+
+impl FrameAdapter {
+    pub fn new(sample_rate: u32) -> Result<Self> {
+        if sample_rate == 0 {
+            return Err(anyhow!("Sample rate must not be zero"));
+        }
+
+        let (input_resampler, output_resampler, priming) = if sample_rate == SAMPLE_RATE {
+            // Samples are output once their frame is complete.
+            (None, None, DTLN_BLOCK_SHIFT - 1)
+        } else {
+            // Frame filling plus a margin for the fractional resampler timing.
+            let frame = (DTLN_BLOCK_SHIFT as u64 * sample_rate as u64).div_ceil(SAMPLE_RATE as u64)
+                as usize;
+            (
+                Some(Resampler::new(sample_rate, SAMPLE_RATE)),
+                Some(Resampler::new(SAMPLE_RATE, sample_rate)),
+                frame + 2,
+            )
+        };
+
+        Ok(FrameAdapter {
+            sample_rate,
+            input_resampler,
+            output_resampler,
+            pending: Vec::new(),
+            output: VecDeque::from(vec![0.0; priming]),
+            priming,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /**
+     * Delay between an input sample and the matching output sample, at the
+     * adapter sample rate. Rounded when the rate is not a multiple of 16khz.
+     * @param internal Latency of the 16khz processing.
+     */
+    pub fn latency(&self, internal: usize) -> usize {
+        let mut internal = internal;
+        if let Some(resampler) = self.output_resampler.as_ref() {
+            internal += resampler.latency();
+        }
+        let scaled = internal as f64 * self.sample_rate as f64 / SAMPLE_RATE as f64;
+        let input = self.input_resampler.as_ref().map_or(0, |r| r.latency());
+        self.priming + input + scaled.round() as usize
+    }
+
+    /**
+     * Process the next input samples. Exactly input.len() samples are
+     * written to output, delayed by latency().
+     * @param process_frames Called with whole frames of 16khz samples, which
+     * it replaces with the processed samples.
+     */
+    pub fn process(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+        mut process_frames: impl FnMut(&mut Vec<f32>),
+    ) {
+        assert!(output.len() >= input.len());
+
+        match self.input_resampler.as_mut() {
+            Some(resampler) => resampler.process(input, &mut self.pending),
+            None => self.pending.extend_from_slice(input),
+        }
+
+        let frames = self.pending.len() / DTLN_BLOCK_SHIFT * DTLN_BLOCK_SHIFT;
+        if frames > 0 {
+            let mut processed = self.pending.drain(..frames).collect::<Vec<f32>>();
+            process_frames(&mut processed);
+            match self.output_resampler.as_mut() {
+                Some(resampler) => {
+                    let mut resampled = Vec::new();
+                    resampler.process(&processed, &mut resampled);
+                    self.output.extend(resampled);
+                }
+                None => self.output.extend(processed),
+            }
+        }
+
+        for sample in output[..input.len()].iter_mut() {
+            // Only reachable if the priming margin is too small.
+            *sample = self.output.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    /**
+     * Drop buffered samples and resampler history.
+     */
+    pub fn reset(&mut self) {
+        *self = FrameAdapter::new(self.sample_rate).expect("Sample rate was validated");
+    }
+}
+
+pub struct AudioPipeline {
+    stages: StageChain,
+    adapter: FrameAdapter,
+}
+
+impl AudioPipeline {
+    pub fn new(config: &PipelineConfig) -> Result<Self> {
+        if config.sample_rate == 0 {
+            return Err(anyhow!("Pipeline sample rate must not be zero"));
+        }
+
+        let mut stages = StageChain::new();
+        let mut engine: Option<DtlnEngine> = None;
+        for (index, stage) in config.stages.iter().enumerate() {
+            // The engine stays open until a stage other than agc follows, so
+            // the AGC can be attached to it.
+            if !matches!(stage, StageConfig::Agc(_)) {
+                if let Some(engine) = engine.take() {
+                    stages.push(Box::new(EngineStage(engine)));
+                }
+            }
+            match stage {
+                StageConfig::DcBlocker => stages.push(Box::new(DcBlocker::new())),
+                StageConfig::HighPass { cutoff_hz } => {
+                    stages.push(Box::new(Biquad::high_pass(*cutoff_hz, HIGH_PASS_Q)))
+                }
+                StageConfig::Gain { db } => stages.push(Box::new(GainStage::new(*db))),
+                StageConfig::Denoise {
+                    native,
+                    min_gain_db,
+                    mix,
+                } => {
+                    let created = if *native {
+                        DtlnEngine::new_native()
+                    } else {
+                        DtlnEngine::new()
+                    };
+                    let mut created =
+                        created.ok_or_else(|| anyhow!("Failed to create DtlnEngine"))?;
+                    if let Some(min_gain_db) = min_gain_db {
+                        created.set_min_gain_db(*min_gain_db);
+                    }
+                    if let Some(mix) = mix {
+                        created.set_mix(*mix);
+                    }
+                    engine = Some(created);
+                }
+                StageConfig::Agc(agc) => {
+                    let Some(engine) = engine.as_mut() else {
+                        return Err(anyhow!(
+                            "Stage {}: agc must directly follow a denoise stage",
+                            index
+                        ));
+                    };
+                    engine.set_agc(Some(*agc));
+                }
+                StageConfig::Limiter(limiter) => stages.push(Box::new(DtlnLimiter::new(*limiter))),
+            }
+        }
+        if let Some(engine) = engine.take() {
+            stages.push(Box::new(EngineStage(engine)));
+        }
+
+        Ok(AudioPipeline {
+            stages,
+            adapter: FrameAdapter::new(config.sample_rate)?,
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: PipelineConfig =
+            serde_json::from_str(json).context("Invalid pipeline config")?;
+        Self::new(&config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let json = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read {}", path.as_ref().display()))?;
+        Self::from_json(&json)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.adapter.sample_rate()
+    }
+
+    /**
+     * Delay between an input sample and the matching output sample, at the
+     * pipeline sample rate. Rounded when the rate is not a multiple of 16khz.
+     */
+    pub fn latency(&self) -> usize {
+        self.adapter.latency(self.stages.latency())
+    }
+
+    /**
+     * Process the next input samples. Exactly input.len() samples are
+     * written to output, delayed by latency().
+     */
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let stages = &mut self.stages;
+        self.adapter
+            .process(input, output, |frames| stages.process(frames));
+    }
+
+    /**
+     * Return every stage to its initial state and drop buffered samples.
+     */
+    pub fn reset(&mut self) {
+        self.stages.reset();
+        self.adapter.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_latency() {
+        let mut pipeline = AudioPipeline::from_json(
+            r#"{"sample_rate": 48000, "stages": [{"type": "gain", "db": 0}]}"#,
+        )
+        .unwrap();
+        let latency = pipeline.latency();
+
+        let input = (0..48000)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 48000.0).sin())
+            .collect::<Vec<f32>>();
+        let mut output = vec![0.0; input.len()];
+        // Uneven chunk sizes exercise the framing.
+        let mut offset = 0;
+        for chunk in [1, 127, 480, 1000, 333].iter().cycle() {
+            let end = (offset + chunk).min(input.len());
+            pipeline.process(&input[offset..end], &mut output[offset..end]);
+            offset = end;
+            if offset == input.len() {
+                break;
+            }
+        }
+
+        assert!(output[..latency - 200].iter().all(|s| *s == 0.0));
+        for i in 4800..input.len() {
+            assert!((output[i] - input[i - latency]).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_agc_placement() {
+        let config = r#"{"stages": [{"type": "dc_blocker"}, {"type": "agc"}]}"#;
+        assert!(AudioPipeline::from_json(config).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+// Common interface of the processing stages that can be chained around
+// DtlnEngine, such as filters before it and the limiter after it.
+
+/**
+ * A processing step operating on 16khz mono samples in place.
+ */
+pub trait AudioStage: Send {
+    fn process(&mut self, samples: &mut [f32]);
+
+    /**
+     * Delay between an input sample and the matching output sample.
+     */
+    fn latency(&self) -> usize {
+        0
+    }
+
+    /**
+     * Clear the signal history, as if no samples had been processed.
+     */
+    fn reset(&mut self) {}
+}
+
+/**
+ * Stages run one after another. A chain is itself a stage, so chains nest.
+ */
+#[derive(Default)]
+pub struct StageChain {
+    stages: Vec<Box<dyn AudioStage>>,
+}
+
+impl StageChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, stage: Box<dyn AudioStage>) {
+        self.stages.push(stage);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+}
+
+impl AudioStage for StageChain {
+    fn process(&mut self, samples: &mut [f32]) {
+        for stage in self.stages.iter_mut() {
+            stage.process(samples);
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.stages.iter().map(|stage| stage.latency()).sum()
+    }
+
+    fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+}
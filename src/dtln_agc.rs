@@ -0,0 +1,160 @@
+// Automatic gain control, run on the denoised output to even out speech
+// levels between talkers and microphones.
+//
+// The speech level is tracked per DTLN_BLOCK_SHIFT block with separate attack
+// and release times, and the gain needed to bring it to the target is applied
+// with a per-sample ramp. When gated by voice activity, the level is only
+// updated during speech, so pauses are not amplified towards the target.
+
+use serde::Deserialize;
+
+use crate::constants::*;
+
+// DTLN sample rate.
+const SAMPLE_RATE: f32 = 16000.0;
+
+// Blocks quieter than this never update the level, even without gating.
+const SILENCE_LEVEL_DB: f32 = -70.0;
+
+// Part of the C API in dtln_capi.rs, hence the C layout.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct AgcConfig {
+    // Desired speech RMS level in dB, relative to a sample value of 1.0.
+    pub target_level_db: f32,
+    // Upper bound of the applied gain in dB.
+    pub max_gain_db: f32,
+    // Time for the level estimate to follow a rise in level.
+    pub attack_ms: f32,
+    // Time for the level estimate to follow a fall in level.
+    pub release_ms: f32,
+    // Only adapt while the voice activity detector reports speech.
+    pub vad_gated: bool,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        AgcConfig {
+            target_level_db: -23.0,
+            max_gain_db: 20.0,
+            attack_ms: 10.0,
+            release_ms: 500.0,
+            vad_gated: true,
+        }
+    }
+}
+
+pub struct DtlnAgc {
+    config: AgcConfig,
+    // Estimated speech level in dB, None until the first update.
+    level_db: Option<f32>,
+    // Linear gain applied at the end of the previous block.
+    gain: f32,
+}
+
+impl DtlnAgc {
+    pub fn new(config: AgcConfig) -> Self {
+        DtlnAgc {
+            config,
+            level_db: None,
+            gain: 1.0,
+        }
+    }
+
+    pub fn config(&self) -> AgcConfig {
+        self.config
+    }
+
+    /**
+     * Change the configuration, keeping the current level estimate and gain
+     * so the change does not cause a jump.
+     */
+    pub fn set_config(&mut self, config: AgcConfig) {
+        self.config = config;
+    }
+
+    /**
+     * Current gain in dB.
+     */
+    pub fn gain_db(&self) -> f32 {
+        20.0 * self.gain.log10()
+    }
+
+    /**
+     * Apply the gain to one DTLN_BLOCK_SHIFT block in place.
+     * @param is_speech Voice activity of the block.
+     */
+    pub fn process_block(&mut self, block: &mut [f32], is_speech: bool) {
+        let energy = block.iter().map(|s| s * s).sum::<f32>() / block.len().max(1) as f32;
+        let block_level_db = 10.0 * energy.max(1e-12).log10();
+
+        if block_level_db > SILENCE_LEVEL_DB && (is_speech || !self.config.vad_gated) {
+            let level_db = match self.level_db {
+                Some(level_db) => {
+                    let time_ms = if block_level_db > level_db {
+                        self.config.attack_ms
+                    } else {
+                        self.config.release_ms
+                    };
+                    let coefficient = smoothing_coefficient(time_ms);
+                    coefficient * level_db + (1.0 - coefficient) * block_level_db
+                }
+                None => block_level_db,
+            };
+            self.level_db = Some(level_db);
+        }
+
+        let target_gain = match self.level_db {
+            Some(level_db) => {
+                let gain_db = (self.config.target_level_db - level_db).min(self.config.max_gain_db);
+                10f32.powf(gain_db / 20.0)
+            }
+            None => 1.0,
+        };
+
+        // Ramp from the previous gain to avoid zipper noise.
+        let step = (target_gain - self.gain) / block.len().max(1) as f32;
+        for sample in block.iter_mut() {
+            self.gain += step;
+            *sample *= self.gain;
+        }
+        self.gain = target_gain;
+    }
+}
+
+// One-pole coefficient reaching ~63% of a step after time_ms.
+fn smoothing_coefficient(time_ms: f32) -> f32 {
+    let block_ms = DTLN_BLOCK_SHIFT as f32 * 1000.0 / SAMPLE_RATE;
+    (-block_ms / time_ms.max(block_ms)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agc_reaches_target_and_holds_in_pauses() {
+        let mut agc = DtlnAgc::new(AgcConfig::default());
+
+        // 2 seconds of quiet "speech", 15 dB below the target.
+        let amplitude = 10f32.powf(-38.0 / 20.0) * std::f32::consts::SQRT_2;
+        let mut block = [0f32; DTLN_BLOCK_SHIFT];
+        for idx in 0..250 {
+            for (i, sample) in block.iter_mut().enumerate() {
+                let t = (idx * DTLN_BLOCK_SHIFT + i) as f32 / SAMPLE_RATE;
+                *sample = amplitude * (2.0 * std::f32::consts::PI * 250.0 * t).sin();
+            }
+            agc.process_block(&mut block, true);
+        }
+        assert!((agc.gain_db() - 15.0).abs() < 0.5);
+
+        // A pause must not change the gain while gated.
+        let mut silence = [1e-4f32; DTLN_BLOCK_SHIFT];
+        let gain_db = agc.gain_db();
+        for _ in 0..100 {
+            agc.process_block(&mut silence, false);
+        }
+        assert_eq!(agc.gain_db(), gain_db);
+    }
+}
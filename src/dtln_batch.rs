@@ -0,0 +1,171 @@
+// Batched inference over many independent DTLN streams.
+//
+// The bundled TFLite models hard-code a batch size of 1 in their RESHAPE
+// operators, so their interpreters cannot be resized to a larger batch. The
+// batched engine therefore runs on the native backend, which evaluates every
+// layer for all streams at once.
+
+use anyhow::{anyhow, Result};
+use realfft::RealFftPlanner;
+
+use crate::constants::*;
+use crate::dtln_engine::{
+    analyze_block, overlap_add, synthesize_block, MODEL_1_DATA, MODEL_2_DATA,
+};
+use crate::dtln_native::DtlnNativeModel;
+
+/**
+ * Denoises several independent streams in lockstep. Each stream has its own
+ * buffers and model states, equivalent to one DtlnEngine per stream, but both
+ * models are invoked once per block for the whole batch.
+ */
+pub struct DtlnBatchEngine {
+    model: DtlnNativeModel,
+    planner: RealFftPlanner<f32>,
+    in_buffers: Vec<[f32; DTLN_BLOCK_LEN]>,
+    out_buffers: Vec<[f32; DTLN_BLOCK_LEN]>,
+    // DTLN_BLOCK_LEN states per stream, stacked back to back.
+    states_1: Vec<f32>,
+    states_2: Vec<f32>,
+}
+
+impl DtlnBatchEngine {
+    pub fn new(stream_count: usize) -> Result<Self> {
+        Ok(DtlnBatchEngine {
+            model: DtlnNativeModel::new(MODEL_1_DATA, MODEL_2_DATA)?,
+            planner: RealFftPlanner::new(),
+            in_buffers: vec![[0.0; DTLN_BLOCK_LEN]; stream_count],
+            out_buffers: vec![[0.0; DTLN_BLOCK_LEN]; stream_count],
+            states_1: vec![0.0; stream_count * DTLN_BLOCK_LEN],
+            states_2: vec![0.0; stream_count * DTLN_BLOCK_LEN],
+        })
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.in_buffers.len()
+    }
+
+    /**
+     * Clear the buffers and model states of one stream, so it can be reused
+     * for new, unrelated audio.
+     */
+    pub fn reset_stream(&mut self, stream: usize) {
+        self.in_buffers[stream] = [0.0; DTLN_BLOCK_LEN];
+        self.out_buffers[stream] = [0.0; DTLN_BLOCK_LEN];
+        self.states_1[stream * DTLN_BLOCK_LEN..(stream + 1) * DTLN_BLOCK_LEN].fill(0.0);
+        self.states_2[stream * DTLN_BLOCK_LEN..(stream + 1) * DTLN_BLOCK_LEN].fill(0.0);
+    }
+
+    /**
+     * Denoise the next samples of every stream.
+     *
+     * @param inputs One slice per stream. All slices must have the same length,
+     * and only whole DTLN_BLOCK_SHIFT blocks are processed.
+     * @param outputs One slice per stream, at least as long as the inputs.
+     */
+    pub fn denoise(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]]) -> Result<()> {
+        let stream_count = self.stream_count();
+        if inputs.len() != stream_count || outputs.len() != stream_count {
+            return Err(anyhow!(
+                "Expected {} streams, got {} inputs and {} outputs",
+                stream_count,
+                inputs.len(),
+                outputs.len()
+            ));
+        }
+
+        let sample_count = inputs.first().map_or(0, |input| input.len());
+        if inputs.iter().any(|input| input.len() != sample_count) {
+            return Err(anyhow!(
+                "All streams must provide the same number of samples"
+            ));
+        }
+        if outputs.iter().any(|output| output.len() < sample_count) {
+            return Err(anyhow!("Output buffer too small"));
+        }
+
+        let mut in_mag = vec![0f32; stream_count * DTLN_FFT_OUT_SIZE];
+        let mut in_phase = vec![0f32; stream_count * DTLN_FFT_OUT_SIZE];
+        let mut out_mask = vec![0f32; stream_count * DTLN_FFT_OUT_SIZE];
+        let mut estimated_block = vec![0f32; stream_count * DTLN_BLOCK_LEN];
+        let mut out_block = vec![0f32; stream_count * DTLN_BLOCK_LEN];
+
+        for idx in 0..sample_count / DTLN_BLOCK_SHIFT {
+            let block = idx * DTLN_BLOCK_SHIFT..(idx + 1) * DTLN_BLOCK_SHIFT;
+
+            for (s, in_buffer) in self.in_buffers.iter_mut().enumerate() {
+                in_buffer.copy_within(DTLN_BLOCK_SHIFT.., 0);
+                in_buffer[(DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT)..]
+                    .copy_from_slice(&inputs[s][block.clone()]);
+
+                analyze_block(
+                    &mut self.planner,
+                    in_buffer,
+                    &mut in_mag[s * DTLN_FFT_OUT_SIZE..(s + 1) * DTLN_FFT_OUT_SIZE],
+                    &mut in_phase[s * DTLN_FFT_OUT_SIZE..(s + 1) * DTLN_FFT_OUT_SIZE],
+                );
+            }
+
+            self.model
+                .run_stage_1(&in_mag, &mut self.states_1, &mut out_mask);
+
+            for s in 0..stream_count {
+                let bins = s * DTLN_FFT_OUT_SIZE..(s + 1) * DTLN_FFT_OUT_SIZE;
+                synthesize_block(
+                    &mut self.planner,
+                    &in_mag[bins.clone()],
+                    &in_phase[bins.clone()],
+                    &out_mask[bins],
+                    &mut estimated_block[s * DTLN_BLOCK_LEN..(s + 1) * DTLN_BLOCK_LEN],
+                );
+            }
+
+            self.model
+                .run_stage_2(&estimated_block, &mut self.states_2, &mut out_block);
+
+            for (s, out_buffer) in self.out_buffers.iter_mut().enumerate() {
+                overlap_add(
+                    out_buffer,
+                    &out_block[s * DTLN_BLOCK_LEN..(s + 1) * DTLN_BLOCK_LEN],
+                );
+                outputs[s][block.clone()].copy_from_slice(&out_buffer[..DTLN_BLOCK_SHIFT]);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_engine::DtlnEngine;
+
+    #[test]
+    fn test_batch_matches_single_stream() -> Result<()> {
+        let streams = (0..3)
+            .map(|_| {
+                (0..4096)
+                    .map(|_| rand::random::<f32>() - 0.5)
+                    .collect::<Vec<f32>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut batch_engine = DtlnBatchEngine::new(streams.len())?;
+        let mut batch_out = vec![vec![0.0; 4096]; streams.len()];
+        let inputs = streams.iter().map(|s| s.as_slice()).collect::<Vec<_>>();
+        let mut outputs = batch_out
+            .iter_mut()
+            .map(|o| o.as_mut_slice())
+            .collect::<Vec<_>>();
+        batch_engine.denoise(&inputs, &mut outputs)?;
+
+        for (samples, batch_out) in streams.iter().zip(&batch_out) {
+            let mut engine = DtlnEngine::new_native().unwrap();
+            let mut out = vec![0.0; samples.len()];
+            engine.denoise(samples, &mut out);
+            assert_eq!(&out, batch_out);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+// Stable C API compiled into the cdylib, for services linking dtln-rs from C,
+// C++ or Go. include/dtln_rs.h is generated from this file by cbindgen, see
+// generate_c_header() in build.rs.
+//
+// Every function returns a DtlnStatus instead of unwinding, panics included,
+// and accepts null pointers by reporting an error. The config structs are
+// defined here rather than reusing the Rust ones, so changes to those don't
+// alter the ABI. DtlnConfig starts with its own size, so a config from another
+// version of the header is rejected instead of misread.
+
+use std::ffi::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::constants::DTLN_BLOCK_SHIFT;
+use crate::dtln_agc::AgcConfig;
+use crate::dtln_engine::DtlnEngine;
+use crate::dtln_filters::PreFilterConfig;
+use crate::dtln_limiter::LimiterConfig;
+
+// Version of the C API, increased on every change to it.
+pub const DTLN_ABI_VERSION: u32 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DtlnStatus {
+    Ok = 0,
+    // A required pointer argument was null.
+    NullPointer = 1,
+    // An argument was out of range, e.g. a sample count which is not a
+    // multiple of DTLN_BLOCK_SHIFT.
+    InvalidArgument = 2,
+    // The engine could not be created, e.g. because the model failed to load.
+    CreateFailed = 3,
+    // An internal error, the engine must not be used any more.
+    Internal = 4,
+}
+
+impl DtlnStatus {
+    // C callers may pass any integer where a status is expected.
+    fn from_u32(status: u32) -> Option<Self> {
+        match status {
+            0 => Some(DtlnStatus::Ok),
+            1 => Some(DtlnStatus::NullPointer),
+            2 => Some(DtlnStatus::InvalidArgument),
+            3 => Some(DtlnStatus::CreateFailed),
+            4 => Some(DtlnStatus::Internal),
+            _ => None,
+        }
+    }
+}
+
+// See AgcConfig.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DtlnAgcConfig {
+    pub target_level_db: f32,
+    pub max_gain_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub vad_gated: bool,
+}
+
+impl From<AgcConfig> for DtlnAgcConfig {
+    fn from(config: AgcConfig) -> Self {
+        DtlnAgcConfig {
+            target_level_db: config.target_level_db,
+            max_gain_db: config.max_gain_db,
+            attack_ms: config.attack_ms,
+            release_ms: config.release_ms,
+            vad_gated: config.vad_gated,
+        }
+    }
+}
+
+impl From<DtlnAgcConfig> for AgcConfig {
+    fn from(config: DtlnAgcConfig) -> Self {
+        AgcConfig {
+            target_level_db: config.target_level_db,
+            max_gain_db: config.max_gain_db,
+            attack_ms: config.attack_ms,
+            release_ms: config.release_ms,
+            vad_gated: config.vad_gated,
+        }
+    }
+}
+
+// See LimiterConfig.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DtlnLimiterConfig {
+    pub ceiling_db: f32,
+    pub lookahead_ms: f32,
+    pub release_ms: f32,
+}
+
+impl From<LimiterConfig> for DtlnLimiterConfig {
+    fn from(config: LimiterConfig) -> Self {
+        DtlnLimiterConfig {
+            ceiling_db: config.ceiling_db,
+            lookahead_ms: config.lookahead_ms,
+            release_ms: config.release_ms,
+        }
+    }
+}
+
+impl From<DtlnLimiterConfig> for LimiterConfig {
+    fn from(config: DtlnLimiterConfig) -> Self {
+        LimiterConfig {
+            ceiling_db: config.ceiling_db,
+            lookahead_ms: config.lookahead_ms,
+            release_ms: config.release_ms,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DtlnConfig {
+    // sizeof(DtlnConfig), set before the config is passed to any function.
+    pub struct_size: usize,
+    // Use the native model implementation instead of TFLite.
+    pub native: bool,
+    // Minimum gain of the suppression mask, -INFINITY for full suppression.
+    pub min_gain_db: f32,
+    // Dry/wet mix, 1.0 outputs only the denoised signal.
+    pub mix: f32,
+    // Remove DC offset from the input.
+    pub dc_blocker: bool,
+    // Cutoff of a high-pass filter on the input, 0 disables it.
+    pub high_pass_hz: f32,
+    pub agc_enabled: bool,
+    pub agc: DtlnAgcConfig,
+    pub limiter_enabled: bool,
+    pub limiter: DtlnLimiterConfig,
+}
+
+impl Default for DtlnConfig {
+    fn default() -> Self {
+        DtlnConfig {
+            struct_size: std::mem::size_of::<DtlnConfig>(),
+            native: false,
+            min_gain_db: f32::NEG_INFINITY,
+            mix: 1.0,
+            dc_blocker: false,
+            high_pass_hz: 0.0,
+            agc_enabled: false,
+            agc: AgcConfig::default().into(),
+            limiter_enabled: false,
+            limiter: LimiterConfig::default().into(),
+        }
+    }
+}
+
+// Check the size a caller set, and the settings which have no sensible
+// fallback.
+fn check_config(config: &DtlnConfig) -> DtlnStatus {
+    if config.struct_size != std::mem::size_of::<DtlnConfig>()
+        || config.min_gain_db.is_nan()
+        || config.mix.is_nan()
+    {
+        return DtlnStatus::InvalidArgument;
+    }
+    DtlnStatus::Ok
+}
+
+fn guard(body: impl FnOnce() -> DtlnStatus) -> DtlnStatus {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|_| {
+        eprintln!("[DTLN] Panic in C API call");
+        DtlnStatus::Internal
+    })
+}
+
+fn configure(engine: &mut DtlnEngine, config: &DtlnConfig) {
+    engine.set_min_gain_db(config.min_gain_db);
+    engine.set_mix(config.mix);
+    engine.set_pre_filter(PreFilterConfig {
+        dc_blocker: config.dc_blocker,
+        high_pass_hz: (config.high_pass_hz > 0.0).then_some(config.high_pass_hz),
+    });
+    engine.set_agc(config.agc_enabled.then_some(config.agc.into()));
+    engine.set_limiter(config.limiter_enabled.then_some(config.limiter.into()));
+}
+
+#[no_mangle]
+pub extern "C" fn dtln_abi_version() -> u32 {
+    DTLN_ABI_VERSION
+}
+
+/// Fill config with the default settings, which match a plain DtlnEngine.
+/// config->struct_size must be set to sizeof(DtlnConfig) first.
+///
+/// # Safety
+/// config must be null or point to a writable DtlnConfig.
+#[no_mangle]
+pub unsafe extern "C" fn dtln_config_default(config: *mut DtlnConfig) -> DtlnStatus {
+    let Some(config) = config.as_mut() else {
+        return DtlnStatus::NullPointer;
+    };
+    // Only struct_size is read, the rest may be uninitialized.
+    if config.struct_size != std::mem::size_of::<DtlnConfig>() {
+        return DtlnStatus::InvalidArgument;
+    }
+    *config = DtlnConfig::default();
+    DtlnStatus::Ok
+}
+
+/// Create an engine. On success *engine receives it, to be released with
+/// dtln_engine_free().
+/// @param config Settings of the engine, null for the defaults. A config with
+/// the wrong struct_size, or a NaN min_gain_db or mix, is rejected with
+/// DTLN_STATUS_INVALID_ARGUMENT.
+///
+/// # Safety
+/// config must be null or point to a DtlnConfig, engine must be null or
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn dtln_engine_new(
+    config: *const DtlnConfig,
+    engine: *mut *mut DtlnEngine,
+) -> DtlnStatus {
+    if engine.is_null() {
+        return DtlnStatus::NullPointer;
+    }
+    *engine = std::ptr::null_mut();
+    let config = config.as_ref().copied().unwrap_or_default();
+    let status = check_config(&config);
+    if status != DtlnStatus::Ok {
+        return status;
+    }
+
+    guard(|| {
+        let created = if config.native {
+            DtlnEngine::new_native()
+        } else {
+            DtlnEngine::new()
+        };
+        let Some(mut created) = created else {
+            return DtlnStatus::CreateFailed;
+        };
+        configure(&mut created, &config);
+        *engine = Box::into_raw(Box::new(created));
+        DtlnStatus::Ok
+    })
+}
+
+/// Change the settings of an engine. Stages keep their state where possible,
+/// except that changing the native flag has no effect. Configs are checked as
+/// by dtln_engine_new().
+///
+/// # Safety
+/// engine must be null or come from dtln_engine_new(), config must be null or
+/// point to a DtlnConfig.
+#[no_mangle]
+pub unsafe extern "C" fn dtln_engine_configure(
+    engine: *mut DtlnEngine,
+    config: *const DtlnConfig,
+) -> DtlnStatus {
+    let (Some(engine), Some(config)) = (engine.as_mut(), config.as_ref()) else {
+        return DtlnStatus::NullPointer;
+    };
+    let status = check_config(config);
+    if status != DtlnStatus::Ok {
+        return status;
+    }
+    guard(|| {
+        configure(engine, config);
+        DtlnStatus::Ok
+    })
+}
+
+/// Denoise 16khz mono samples. The output is delayed by
+/// dtln_engine_latency() samples.
+/// @param sample_count Length of input and output, a multiple of
+/// DTLN_BLOCK_SHIFT. input and output may be the same buffer.
+///
+/// # Safety
+/// engine must be null or come from dtln_engine_new(). input and output must
+/// be null or hold sample_count floats.
+#[no_mangle]
+pub unsafe extern "C" fn dtln_engine_process(
+    engine: *mut DtlnEngine,
+    input: *const f32,
+    output: *mut f32,
+    sample_count: usize,
+) -> DtlnStatus {
+    let Some(engine) = engine.as_mut() else {
+        return DtlnStatus::NullPointer;
+    };
+    if sample_count == 0 {
+        return DtlnStatus::Ok;
+    }
+    if input.is_null() || output.is_null() {
+        return DtlnStatus::NullPointer;
+    }
+    if !sample_count.is_multiple_of(DTLN_BLOCK_SHIFT) {
+        return DtlnStatus::InvalidArgument;
+    }
+
+    guard(|| {
+        // Copied first, as the buffers may alias.
+        let samples = std::slice::from_raw_parts(input, sample_count).to_vec();
+        let output = std::slice::from_raw_parts_mut(output, sample_count);
+        engine.denoise(&samples, output);
+        DtlnStatus::Ok
+    })
+}
+
+/// Delay between an input sample and the matching output sample, 0 for a null
+/// engine.
+///
+/// # Safety
+/// engine must be null or come from dtln_engine_new().
+#[no_mangle]
+pub unsafe extern "C" fn dtln_engine_latency(engine: *const DtlnEngine) -> usize {
+    engine.as_ref().map_or(0, |engine| engine.latency())
+}
+
+/// Release an engine created by dtln_engine_new(). Null is ignored.
+///
+/// # Safety
+/// engine must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn dtln_engine_free(engine: *mut DtlnEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// A static, human readable description of a DtlnStatus value. Values which
+/// are not a DtlnStatus give "unknown status".
+#[no_mangle]
+pub extern "C" fn dtln_status_string(status: u32) -> *const c_char {
+    let message: &'static [u8] = match DtlnStatus::from_u32(status) {
+        Some(DtlnStatus::Ok) => b"ok\0",
+        Some(DtlnStatus::NullPointer) => b"null pointer argument\0",
+        Some(DtlnStatus::InvalidArgument) => b"invalid argument\0",
+        Some(DtlnStatus::CreateFailed) => b"failed to create engine\0",
+        Some(DtlnStatus::Internal) => b"internal error\0",
+        None => b"unknown status\0",
+    };
+    message.as_ptr().cast()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capi_process() {
+        unsafe {
+            let mut config = std::mem::zeroed::<DtlnConfig>();
+            assert_eq!(
+                dtln_config_default(&mut config),
+                DtlnStatus::InvalidArgument
+            );
+            config.struct_size = std::mem::size_of::<DtlnConfig>();
+            assert_eq!(dtln_config_default(&mut config), DtlnStatus::Ok);
+            config.native = true;
+            config.limiter_enabled = true;
+
+            let mut engine = std::ptr::null_mut();
+            for invalid in [
+                DtlnConfig {
+                    struct_size: config.struct_size - 1,
+                    ..config
+                },
+                DtlnConfig {
+                    min_gain_db: f32::NAN,
+                    ..config
+                },
+                DtlnConfig {
+                    mix: f32::NAN,
+                    ..config
+                },
+            ] {
+                assert_eq!(
+                    dtln_engine_new(&invalid, &mut engine),
+                    DtlnStatus::InvalidArgument
+                );
+                assert!(engine.is_null());
+            }
+            assert_eq!(dtln_engine_new(&config, &mut engine), DtlnStatus::Ok);
+            assert_eq!(dtln_engine_latency(engine), 384 + 80);
+
+            let mut samples = (0..1024)
+                .map(|i| (i as f32 * 0.05).sin() * 0.1)
+                .collect::<Vec<f32>>();
+            let ptr = samples.as_mut_ptr();
+            assert_eq!(dtln_engine_process(engine, ptr, ptr, 1024), DtlnStatus::Ok);
+            assert_eq!(
+                dtln_engine_process(engine, ptr, ptr, 100),
+                DtlnStatus::InvalidArgument
+            );
+            assert_eq!(
+                dtln_engine_process(std::ptr::null_mut(), ptr, ptr, 128),
+                DtlnStatus::NullPointer
+            );
+            dtln_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_capi_status_string() {
+        for status in 0..=4 {
+            let message = unsafe { std::ffi::CStr::from_ptr(dtln_status_string(status)) };
+            assert_eq!(DtlnStatus::from_u32(status).unwrap() as u32, status);
+            assert_ne!(message.to_str().unwrap(), "unknown status");
+        }
+        let message = unsafe { std::ffi::CStr::from_ptr(dtln_status_string(5)) };
+        assert_eq!(message.to_str().unwrap(), "unknown status");
+    }
+
+    #[test]
+    fn test_header_up_to_date() {
+        // Written by build.rs, DTLN_UPDATE_HEADER=1 cargo build updates the
+        // checked-in copy.
+        let generated = std::fs::read_to_string(concat!(env!("OUT_DIR"), "/dtln_rs.h"))
+            .expect("build.rs did not generate dtln_rs.h");
+        assert_eq!(generated, include_str!("../include/dtln_rs.h"));
+    }
+}
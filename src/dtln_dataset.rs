@@ -0,0 +1,379 @@
+// Synthesis of paired clean/noisy speech, and evaluation of the engine over
+// such a set.
+//
+// A dataset directory contains clean/ and noisy/ wav files and a manifest.csv
+// listing the pairs, with paths relative to the directory.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::dtln_metrics::{evaluate, QualityMetrics};
+use crate::dtln_offline::{denoise_offline, OfflineOptions};
+use crate::dtln_utilities::{read_wav_to_pcm32, write_pcm16_to_wav};
+
+// DTLN sample rate.
+const SAMPLE_RATE: u32 = 16000;
+
+// read_wav_to_pcm32 maps 16 bit full scale to +-0.5, keep mixtures below it.
+const PEAK_LIMIT: f32 = 0.49;
+
+const MANIFEST_NAME: &str = "manifest.csv";
+const MANIFEST_HEADER: &str = "id,clean_path,noisy_path,noise,snr_db";
+
+pub struct SynthesisOptions {
+    // Every clean file is mixed with every noise file at each of these SNRs.
+    pub snrs_db: Vec<f32>,
+    // RMS level of the noisy mixture, relative to a sample value of 1.0.
+    pub target_rms_db: f32,
+    // Seeds the choice of noise excerpts, so datasets can be reproduced.
+    pub seed: u64,
+}
+
+impl Default for SynthesisOptions {
+    fn default() -> Self {
+        SynthesisOptions {
+            snrs_db: vec![0.0, 5.0, 10.0, 20.0],
+            target_rms_db: -26.0,
+            seed: 0,
+        }
+    }
+}
+
+pub struct ManifestEntry {
+    pub id: String,
+    pub clean_path: PathBuf,
+    pub noisy_path: PathBuf,
+    pub noise: String,
+    pub snr_db: f32,
+}
+
+#[derive(Serialize)]
+pub struct EntryEvaluation {
+    pub id: String,
+    pub snr_db: f32,
+    // The unprocessed mixture against the clean reference, None on error.
+    pub noisy: Option<QualityMetrics>,
+    // The denoised mixture against the clean reference, None on error.
+    pub denoised: Option<QualityMetrics>,
+    // Why the entry could not be evaluated, e.g. an unreadable file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/**
+ * Mix clean speech with noise at the given SNR, then scale both by the same
+ * gain so the mixture has the target RMS level without exceeding PEAK_LIMIT.
+ *
+ * @param noise Looped or cut to the length of the clean signal, starting at
+ * a random offset.
+ * @returns The scaled clean signal and the noisy mixture.
+ */
+pub fn mix_at_snr(
+    clean: &[f32],
+    noise: &[f32],
+    snr_db: f32,
+    target_rms_db: f32,
+    rng: &mut impl Rng,
+) -> Result<(Vec<f32>, Vec<f32>)> {
+    let clean_rms = rms(clean);
+    if clean_rms == 0.0 || rms(noise) == 0.0 {
+        return Err(anyhow!("Clean and noise signals must not be silent"));
+    }
+
+    let offset = rng.gen_range(0..noise.len());
+    let noise = noise
+        .iter()
+        .cycle()
+        .skip(offset)
+        .take(clean.len())
+        .copied()
+        .collect::<Vec<f32>>();
+
+    let noise_gain = clean_rms / (rms(&noise).max(f32::MIN_POSITIVE) * 10f32.powf(snr_db / 20.0));
+    let mut noisy = clean
+        .iter()
+        .zip(&noise)
+        .map(|(clean, noise)| clean + noise * noise_gain)
+        .collect::<Vec<f32>>();
+
+    let peak = noisy.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+    let gain = (10f32.powf(target_rms_db / 20.0) / rms(&noisy)).min(PEAK_LIMIT / peak);
+
+    let clean = clean.iter().map(|s| s * gain).collect::<Vec<f32>>();
+    for sample in noisy.iter_mut() {
+        *sample *= gain;
+    }
+    Ok((clean, noisy))
+}
+
+/**
+ * Mix every clean file with every noise file at every SNR of the options,
+ * and write the pairs and manifest to output_dir.
+ */
+pub fn synthesize_dataset(
+    clean_paths: &[String],
+    noise_paths: &[String],
+    output_dir: &str,
+    options: &SynthesisOptions,
+) -> Result<Vec<ManifestEntry>> {
+    let output_dir = Path::new(output_dir);
+    fs::create_dir_all(output_dir.join("clean"))?;
+    fs::create_dir_all(output_dir.join("noisy"))?;
+
+    let noises = noise_paths
+        .iter()
+        .map(|path| Ok((file_stem(path), read_16khz_wav(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut entries = vec![];
+    for clean_path in clean_paths {
+        let clean = read_16khz_wav(clean_path)?;
+        for (noise_name, noise) in &noises {
+            for snr_db in &options.snrs_db {
+                let (clean, noisy) =
+                    mix_at_snr(&clean, noise, *snr_db, options.target_rms_db, &mut rng)
+                        .with_context(|| format!("Unable to mix {}", clean_path))?;
+
+                let id = format!("{}_{}_{}dB", file_stem(clean_path), noise_name, snr_db);
+                let entry = ManifestEntry {
+                    clean_path: Path::new("clean").join(format!("{}.wav", id)),
+                    noisy_path: Path::new("noisy").join(format!("{}.wav", id)),
+                    noise: noise_name.clone(),
+                    snr_db: *snr_db,
+                    id,
+                };
+                write_wav(&clean, &output_dir.join(&entry.clean_path))?;
+                write_wav(&noisy, &output_dir.join(&entry.noisy_path))?;
+                entries.push(entry);
+            }
+        }
+    }
+
+    let mut manifest = BufWriter::new(File::create(output_dir.join(MANIFEST_NAME))?);
+    writeln!(manifest, "{}", MANIFEST_HEADER)?;
+    for entry in &entries {
+        writeln!(
+            manifest,
+            "{},{},{},{},{}",
+            entry.id,
+            entry.clean_path.display(),
+            entry.noisy_path.display(),
+            entry.noise,
+            entry.snr_db
+        )?;
+    }
+    manifest.flush()?;
+
+    Ok(entries)
+}
+
+pub fn read_manifest(dataset_dir: &str) -> Result<Vec<ManifestEntry>> {
+    let path = Path::new(dataset_dir).join(MANIFEST_NAME);
+    let file = File::open(&path).with_context(|| format!("Unable to open {}", path.display()))?;
+
+    let mut entries = vec![];
+    for line in BufReader::new(file).lines().skip(1) {
+        let line = line?;
+        let fields = line.split(',').collect::<Vec<_>>();
+        let [id, clean_path, noisy_path, noise, snr_db] = fields[..] else {
+            return Err(anyhow!("Malformed manifest line: {}", line));
+        };
+        entries.push(ManifestEntry {
+            id: id.to_string(),
+            clean_path: PathBuf::from(clean_path),
+            noisy_path: PathBuf::from(noisy_path),
+            noise: noise.to_string(),
+            snr_db: snr_db.parse()?,
+        });
+    }
+    Ok(entries)
+}
+
+/**
+ * Denoise every noisy file of a dataset offline, and measure both the noisy
+ * and the denoised audio against the clean reference. Entries which fail,
+ * e.g. because a file is unreadable, are returned with their error.
+ */
+pub fn evaluate_dataset(
+    dataset_dir: &str,
+    options: &OfflineOptions,
+) -> Result<Vec<EntryEvaluation>> {
+    let dir = Path::new(dataset_dir);
+    let evaluate_entry = |entry: &ManifestEntry| -> Result<(QualityMetrics, QualityMetrics)> {
+        let clean = read_16khz_wav(&dir.join(&entry.clean_path).to_string_lossy())?;
+        let noisy = read_16khz_wav(&dir.join(&entry.noisy_path).to_string_lossy())?;
+        let denoised = denoise_offline(&noisy, options)?.samples;
+        Ok((evaluate(&clean, &noisy)?, evaluate(&clean, &denoised)?))
+    };
+
+    Ok(read_manifest(dataset_dir)?
+        .into_iter()
+        .map(|entry| {
+            let (noisy, denoised, error) = match evaluate_entry(&entry) {
+                Ok((noisy, denoised)) => (Some(noisy), Some(denoised), None),
+                Err(e) => (None, None, Some(format!("{:#}", e))),
+            };
+            EntryEvaluation {
+                id: entry.id,
+                snr_db: entry.snr_db,
+                noisy,
+                denoised,
+                error,
+            }
+        })
+        .collect())
+}
+
+/**
+ * Mean of each metric over the entries of a dataset evaluation which did not
+ * fail.
+ * @returns The means over the noisy and over the denoised audio.
+ */
+pub fn mean_metrics(evaluations: &[EntryEvaluation]) -> (QualityMetrics, QualityMetrics) {
+    let mean = |metrics: &mut dyn Iterator<Item = QualityMetrics>| {
+        let mut sum = QualityMetrics::default();
+        let mut count = 0;
+        for m in metrics {
+            sum.snr += m.snr;
+            sum.si_sdr += m.si_sdr;
+            sum.segmental_snr += m.segmental_snr;
+            sum.log_spectral_distance += m.log_spectral_distance;
+            sum.intelligibility += m.intelligibility;
+            count += 1;
+        }
+        let count = count.max(1) as f64;
+        QualityMetrics {
+            snr: sum.snr / count,
+            si_sdr: sum.si_sdr / count,
+            segmental_snr: sum.segmental_snr / count,
+            log_spectral_distance: sum.log_spectral_distance / count,
+            intelligibility: sum.intelligibility / count,
+        }
+    };
+    (
+        mean(&mut evaluations.iter().filter_map(|e| e.noisy)),
+        mean(&mut evaluations.iter().filter_map(|e| e.denoised)),
+    )
+}
+
+/**
+ * The per entry evaluations and their means as a JSON object. Non-finite
+ * metrics are written as null.
+ */
+pub fn evaluations_to_json(evaluations: &[EntryEvaluation]) -> String {
+    let (noisy, denoised) = mean_metrics(evaluations);
+    serde_json::json!({
+        "entries": evaluations,
+        "mean": {"noisy": noisy, "denoised": denoised},
+    })
+    .to_string()
+}
+
+fn read_16khz_wav(path: &str) -> Result<Vec<f32>> {
+    let mut samples = vec![];
+    let sample_rate = read_wav_to_pcm32(path, &mut samples)
+        .with_context(|| format!("Unable to read {}", path))?;
+    if sample_rate != SAMPLE_RATE {
+        return Err(anyhow!(
+            "{} is {}hz, expected {}hz",
+            path,
+            sample_rate,
+            SAMPLE_RATE
+        ));
+    }
+    Ok(samples)
+}
+
+fn write_wav(samples: &[f32], path: &Path) -> Result<()> {
+    write_pcm16_to_wav(samples, &path.to_string_lossy(), SAMPLE_RATE)
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+// Name of a file without directory or extension, usable in ids and the manifest.
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map_or_else(
+            || path.to_string(),
+            |stem| stem.to_string_lossy().to_string(),
+        )
+        .replace(',', "_")
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_metrics::snr;
+
+    #[test]
+    fn test_mix_at_snr() -> Result<()> {
+        let clean = (0..16000)
+            .map(|i| 0.3 * (i as f32 * 0.05).sin())
+            .collect::<Vec<f32>>();
+        let noise = (0..5000)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let (scaled_clean, noisy) = mix_at_snr(&clean, &noise, 5.0, -26.0, &mut rng)?;
+
+        assert_eq!(noisy.len(), clean.len());
+        assert!((snr(&scaled_clean, &noisy) - 5.0).abs() < 0.01);
+        assert!((20.0 * rms(&noisy).log10() + 26.0).abs() < 0.01);
+        assert!(noisy.iter().all(|s| s.abs() <= PEAK_LIMIT));
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_dataset_reports_entry_errors() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dtln_dataset_{}", std::process::id()));
+        fs::create_dir_all(dir.join("clean"))?;
+        let clean = (0..16000)
+            .map(|i| 0.3 * (i as f32 * 0.05).sin())
+            .collect::<Vec<f32>>();
+        write_wav(&clean, &dir.join("clean/a.wav"))?;
+        fs::write(
+            dir.join(MANIFEST_NAME),
+            format!(
+                "{}\n\"quoted\",clean/a.wav,clean/a.wav,none,0\nmissing,clean/a.wav,noisy/b.wav,none,0\n",
+                MANIFEST_HEADER
+            ),
+        )?;
+
+        let options = OfflineOptions {
+            native: true,
+            ..OfflineOptions::default()
+        };
+        let evaluations = evaluate_dataset(&dir.to_string_lossy(), &options)?;
+        fs::remove_dir_all(&dir)?;
+
+        assert_eq!(evaluations.len(), 2);
+        assert!(evaluations[0].error.is_none());
+        assert!(evaluations[1].noisy.is_none());
+        assert!(evaluations[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("noisy/b.wav"));
+
+        let json = serde_json::from_str::<serde_json::Value>(&evaluations_to_json(&evaluations))?;
+        assert_eq!(json["entries"][0]["id"], "\"quoted\"");
+        assert!(json["entries"][1]["denoised"].is_null());
+        assert_eq!(
+            json["mean"]["noisy"]["snr"],
+            evaluations[0].noisy.unwrap().snr
+        );
+        Ok(())
+    }
+}
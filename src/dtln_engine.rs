@@ -1,15 +1,47 @@
 // dtln_engine.rs
+use std::collections::VecDeque;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 use std::ptr;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 use std::slice;
 
 use anyhow::Result;
 use num::Complex;
 use realfft::RealFftPlanner;
 
+use crate::audio_stage::{AudioStage, StageChain};
 use crate::constants::*;
+use crate::dtln_agc::{AgcConfig, DtlnAgc};
+use crate::dtln_filters::PreFilterConfig;
+use crate::dtln_limiter::{ClipStats, DtlnLimiter, LimiterConfig};
+use crate::dtln_native::DtlnNativeModel;
+use crate::dtln_observer::{BlockObserver, BlockSpectra};
+use crate::dtln_vad::{DtlnVad, VadResult};
+use crate::param_ramp::ParamRamp;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 use crate::tflite::*;
 
-pub struct DtlnEngine {
+pub(crate) static MODEL_1_DATA: &[u8] = include_bytes!("../model/model_quant_1.tflite");
+pub(crate) static MODEL_2_DATA: &[u8] = include_bytes!("../model/model_quant_2.tflite");
+
+// Blocks taken to reach a new mask floor (~32 ms).
+const MASK_FLOOR_RAMP_BLOCKS: u32 = 4;
+
+// Samples taken to reach a new dry/wet mix (~32 ms).
+const MIX_RAMP_SAMPLES: u32 = 512;
+
+// Blocks from a block entering the model to its output. VAD decisions are
+// delayed by this much before gating the AGC, so they match the block it sees.
+const VAD_DELAY_BLOCKS: usize = (DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT) / DTLN_BLOCK_SHIFT;
+
+// Silence the AudioStage output is primed with, so calls of any length return
+// as many samples as they are given while a block fills. Matches FrameAdapter.
+const STAGE_PRIMING: usize = DTLN_BLOCK_SHIFT - 1;
+
+// Runs the two DTLN models through the TFLite interpreter, which is not
+// available on wasm32-unknown-unknown.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+struct TfliteBackend {
     model1: *const TfLiteModel,
     interpreter_1: *mut TfLiteInterpreter,
     model2: *const TfLiteModel,
@@ -18,18 +50,55 @@ pub struct DtlnEngine {
     output_details_1: [*const TfLiteTensor; 2],
     details2: [*mut TfLiteTensor; 2],
     output_details_2: [*const TfLiteTensor; 2],
+}
+
+// The inference path used for the two model stages of infer().
+enum DtlnBackend {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    Tflite(TfliteBackend),
+    Native(Box<DtlnNativeModel>),
+}
+
+pub struct DtlnEngine {
+    backend: DtlnBackend,
     valid: bool,
     in_buffer: [f32; DTLN_BLOCK_LEN],
     out_buffer: [f32; DTLN_BLOCK_LEN],
     states_1: [f32; DTLN_BLOCK_LEN],
     states_2: [f32; DTLN_BLOCK_LEN],
+    // Stages run on the input before it reaches the model.
+    pre_stages: StageChain,
+    // Lowest linear gain the model 1 mask may apply.
+    mask_floor: ParamRamp,
+    // Fraction of denoised signal in the output, the rest is the input.
+    mix: ParamRamp,
+    vad: DtlnVad,
+    // Voice activity over the most recent denoise() call.
+    vad_result: VadResult,
+    // is_speech of the last VAD_DELAY_BLOCKS blocks, oldest first.
+    vad_delay: VecDeque<bool>,
+    observer: Option<Box<dyn BlockObserver>>,
+    // Optional gain control applied to the mixed output.
+    agc: Option<DtlnAgc>,
+    // Optional peak limiter, run after the AGC.
+    limiter: Option<DtlnLimiter>,
+    // Output samples beyond +-1.0.
+    clipped_samples: u64,
+    // AudioStage input waiting for a whole DTLN_BLOCK_SHIFT block.
+    stage_pending: Vec<f32>,
+    // Denoised AudioStage samples, primed with STAGE_PRIMING of silence.
+    stage_output: VecDeque<f32>,
+    // Copy of the input of denoise_in_place(), kept to avoid allocating.
+    scratch: Vec<f32>,
 }
 
-unsafe impl Send for DtlnEngine {}
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+unsafe impl Send for TfliteBackend {}
 
-impl DtlnEngine {
-    pub fn new() -> Option<Self> {
-        let model1_data = include_bytes!("../model/model_quant_1.tflite");
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl TfliteBackend {
+    fn new(threads: usize) -> Option<Self> {
+        let model1_data = MODEL_1_DATA;
         let model1_size = model1_data.len();
 
         let model1 = unsafe { TfLiteModelCreate(model1_data.as_ptr() as *const _, model1_size) };
@@ -39,7 +108,7 @@ impl DtlnEngine {
         }
 
         let options = unsafe { TfLiteInterpreterOptionsCreate() };
-        unsafe { TfLiteInterpreterOptionsSetNumThreads(options, 1) };
+        unsafe { TfLiteInterpreterOptionsSetNumThreads(options, threads.max(1) as i32) };
 
         let interpreter_1 = unsafe { TfLiteInterpreterCreate(model1, options) };
         if interpreter_1.is_null() {
@@ -60,7 +129,7 @@ impl DtlnEngine {
             return None;
         }
 
-        let model2_data = include_bytes!("../model/model_quant_2.tflite");
+        let model2_data = MODEL_2_DATA;
         let model2_size = model2_data.len();
 
         let model2 = unsafe { TfLiteModelCreate(model2_data.as_ptr() as *const _, model2_size) };
@@ -110,7 +179,7 @@ impl DtlnEngine {
 
         unsafe { TfLiteInterpreterOptionsDelete(options) };
 
-        Some(DtlnEngine {
+        Some(TfliteBackend {
             model1,
             interpreter_1,
             model2,
@@ -119,18 +188,339 @@ impl DtlnEngine {
             output_details_1: [output_details_1_0, output_details_1_1],
             details2: [details2_0, details2_1],
             output_details_2: [output_details_2_0, output_details_2_1],
+        })
+    }
+
+    fn run_stage_1(
+        &mut self,
+        in_mag: &[f32],
+        states: &mut [f32],
+        out_mask: &mut [f32],
+    ) -> Result<()> {
+        // Prepare inputs for model 1
+        let in_mag_ptr = unsafe { TfLiteTensorData(self.details1[0]) as *mut f32 };
+        unsafe {
+            ptr::copy_nonoverlapping(in_mag.as_ptr(), in_mag_ptr, DTLN_FFT_OUT_SIZE);
+        }
+        let states1_ptr = unsafe { TfLiteTensorData(self.details1[1]) as *mut f32 };
+        unsafe {
+            ptr::copy_nonoverlapping(states.as_ptr(), states1_ptr, DTLN_BLOCK_LEN);
+        }
+
+        // Invoke model 1
+        unsafe { TfLiteInterpreterInvoke(self.interpreter_1) }.to_result()?;
+
+        // Get outputs
+        let out_mask_ptr = unsafe { TfLiteTensorData(self.output_details_1[0]) as *const f32 };
+        out_mask.copy_from_slice(unsafe { slice::from_raw_parts(out_mask_ptr, DTLN_FFT_OUT_SIZE) });
+
+        let out_states1_ptr = unsafe { TfLiteTensorData(self.output_details_1[1]) as *const f32 };
+        unsafe {
+            ptr::copy_nonoverlapping(out_states1_ptr, states.as_mut_ptr(), DTLN_BLOCK_LEN);
+        }
+        Ok(())
+    }
+
+    fn run_stage_2(
+        &mut self,
+        block: &[f32],
+        states: &mut [f32],
+        out_block: &mut [f32],
+    ) -> Result<()> {
+        // Prepare inputs for model 2
+        let est_block_ptr = unsafe { TfLiteTensorData(self.details2[0]) as *mut f32 };
+        unsafe {
+            ptr::copy_nonoverlapping(block.as_ptr(), est_block_ptr, DTLN_BLOCK_LEN);
+        }
+        let states2_ptr = unsafe { TfLiteTensorData(self.details2[1]) as *mut f32 };
+        unsafe {
+            ptr::copy_nonoverlapping(states.as_ptr(), states2_ptr, DTLN_BLOCK_LEN);
+        }
+
+        // Invoke model 2
+        unsafe { TfLiteInterpreterInvoke(self.interpreter_2) }.to_result()?;
+
+        // Get outputs
+        let out_block_ptr = unsafe { TfLiteTensorData(self.output_details_2[0]) as *const f32 };
+        out_block.copy_from_slice(unsafe { slice::from_raw_parts(out_block_ptr, DTLN_BLOCK_LEN) });
+
+        let out_states2_ptr = unsafe { TfLiteTensorData(self.output_details_2[1]) as *const f32 };
+        unsafe {
+            ptr::copy_nonoverlapping(out_states2_ptr, states.as_mut_ptr(), DTLN_BLOCK_LEN);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Drop for TfliteBackend {
+    fn drop(&mut self) {
+        unsafe {
+            TfLiteInterpreterDelete(self.interpreter_1);
+            TfLiteInterpreterDelete(self.interpreter_2);
+            TfLiteModelDelete(self.model1);
+            TfLiteModelDelete(self.model2);
+        }
+    }
+}
+
+impl DtlnBackend {
+    fn run_stage_1(
+        &mut self,
+        in_mag: &[f32],
+        states: &mut [f32],
+        out_mask: &mut [f32],
+    ) -> Result<()> {
+        match self {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            DtlnBackend::Tflite(backend) => backend.run_stage_1(in_mag, states, out_mask),
+            DtlnBackend::Native(model) => {
+                model.run_stage_1(in_mag, states, out_mask);
+                Ok(())
+            }
+        }
+    }
+
+    fn run_stage_2(
+        &mut self,
+        block: &[f32],
+        states: &mut [f32],
+        out_block: &mut [f32],
+    ) -> Result<()> {
+        match self {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            DtlnBackend::Tflite(backend) => backend.run_stage_2(block, states, out_block),
+            DtlnBackend::Native(model) => {
+                model.run_stage_2(block, states, out_block);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl DtlnEngine {
+    /**
+     * Create an engine which runs the models through the TFLite interpreter.
+     */
+    pub fn new() -> Option<Self> {
+        Self::with_threads(1)
+    }
+
+    /**
+     * Like new(), with interpreters that may use up to threads threads each.
+     * The models are small, so this only helps on slow cores.
+     */
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn with_threads(threads: usize) -> Option<Self> {
+        let backend = TfliteBackend::new(threads)?;
+        Some(Self::with_backend(DtlnBackend::Tflite(backend)))
+    }
+
+    /**
+     * Without TFLite on wasm32-unknown-unknown, engines use the native
+     * implementation, see new_native().
+     */
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    pub fn with_threads(_threads: usize) -> Option<Self> {
+        Self::new_native()
+    }
+
+    /**
+     * Create an engine which runs the models with the native Rust
+     * implementation in dtln_native.rs, bypassing the TFLite interpreter.
+     */
+    pub fn new_native() -> Option<Self> {
+        match DtlnNativeModel::new(MODEL_1_DATA, MODEL_2_DATA) {
+            Ok(model) => Some(Self::with_backend(DtlnBackend::Native(Box::new(model)))),
+            Err(e) => {
+                eprintln!("[DTLN] Failed to load native model: {}", e);
+                None
+            }
+        }
+    }
+
+    fn with_backend(backend: DtlnBackend) -> Self {
+        DtlnEngine {
+            backend,
             valid: true,
             in_buffer: [0.0; DTLN_BLOCK_LEN],
             out_buffer: [0.0; DTLN_BLOCK_LEN],
             states_1: [0.0; DTLN_BLOCK_LEN],
             states_2: [0.0; DTLN_BLOCK_LEN],
-        })
+            pre_stages: StageChain::new(),
+            mask_floor: ParamRamp::new(0.0, MASK_FLOOR_RAMP_BLOCKS),
+            mix: ParamRamp::new(1.0, MIX_RAMP_SAMPLES),
+            vad: DtlnVad::new(),
+            vad_result: VadResult::default(),
+            vad_delay: VecDeque::from(vec![false; VAD_DELAY_BLOCKS]),
+            observer: None,
+            agc: None,
+            limiter: None,
+            clipped_samples: 0,
+            stage_pending: Vec::with_capacity(DTLN_BLOCK_SHIFT),
+            stage_output: VecDeque::from(vec![0.0; STAGE_PRIMING]),
+            scratch: Vec::new(),
+        }
+    }
+
+    /**
+     * Limit how much the model may attenuate any frequency bin, which sets
+     * the noise suppression level. Changes are ramped in over a few blocks.
+     *
+     * @param min_gain_db Minimum gain of the suppression mask, e.g. -12.0.
+     * f32::NEG_INFINITY (the default) applies the mask at full strength. NaN
+     * is ignored.
+     */
+    pub fn set_min_gain_db(&mut self, min_gain_db: f32) {
+        if min_gain_db.is_nan() {
+            return;
+        }
+        let gain = 10f32.powf(min_gain_db.min(0.0) / 20.0);
+        self.mask_floor.set_target(gain);
+    }
+
+    /**
+     * Blend the denoised output with the time aligned input signal. Changes
+     * are ramped in to avoid zipper noise.
+     *
+     * @param wet 1.0 (the default) outputs only the denoised signal, 0.0 only
+     * the original signal. NaN is ignored.
+     */
+    pub fn set_mix(&mut self, wet: f32) {
+        if wet.is_nan() {
+            return;
+        }
+        self.mix.set_target(wet.clamp(0.0, 1.0));
+    }
+
+    /**
+     * Replace the stages run on the input before the model, such as the
+     * filters of set_pre_filter(). They must not add latency.
+     */
+    pub fn set_pre_stages(&mut self, stages: StageChain) {
+        assert_eq!(stages.latency(), 0, "Pre-stages must not add latency");
+        self.pre_stages = stages;
+    }
+
+    /**
+     * Remove DC offset and low frequency rumble from the input before the
+     * model. Replaces any previous pre-stages.
+     */
+    pub fn set_pre_filter(&mut self, config: PreFilterConfig) {
+        self.set_pre_stages(config.build());
+    }
+
+    /**
+     * Enable automatic gain control of the output, or disable it with None.
+     * Reconfiguring an enabled AGC keeps its current gain.
+     */
+    pub fn set_agc(&mut self, config: Option<AgcConfig>) {
+        match (config, self.agc.as_mut()) {
+            (Some(config), Some(agc)) => agc.set_config(config),
+            (config, _) => self.agc = config.map(DtlnAgc::new),
+        }
+    }
+
+    /**
+     * Enable the look-ahead peak limiter, or disable it with None. Changing
+     * the look-ahead recreates the limiter, which changes latency().
+     */
+    pub fn set_limiter(&mut self, config: Option<LimiterConfig>) {
+        match (config, self.limiter.as_mut()) {
+            (Some(config), Some(limiter))
+                if limiter.config().lookahead_ms == config.lookahead_ms =>
+            {
+                limiter.set_config(config)
+            }
+            (config, _) => self.limiter = config.map(DtlnLimiter::new),
+        }
+    }
+
+    /**
+     * Delay between an input sample and the matching output sample.
+     */
+    pub fn latency(&self) -> usize {
+        DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT + self.limiter.as_ref().map_or(0, |l| l.latency())
+    }
+
+    /**
+     * Clipping counters since creation or the last reset_clip_stats().
+     */
+    pub fn clip_stats(&self) -> ClipStats {
+        ClipStats {
+            clipped_samples: self.clipped_samples,
+            limited_samples: self.limiter.as_ref().map_or(0, |l| l.limited_samples()),
+        }
+    }
+
+    pub fn reset_clip_stats(&mut self) {
+        self.clipped_samples = 0;
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.reset_limited_samples();
+        }
+    }
+
+    /**
+     * Return to the state of a new engine, e.g. before processing an
+     * unrelated stream. Settings and the clipping counters are kept, and
+     * ramps jump to their targets.
+     */
+    pub fn reset(&mut self) {
+        self.in_buffer.fill(0.0);
+        self.out_buffer.fill(0.0);
+        self.states_1.fill(0.0);
+        self.states_2.fill(0.0);
+        self.pre_stages.reset();
+        self.mask_floor = ParamRamp::new(self.mask_floor.target(), MASK_FLOOR_RAMP_BLOCKS);
+        self.mix = ParamRamp::new(self.mix.target(), MIX_RAMP_SAMPLES);
+        self.vad = DtlnVad::new();
+        self.vad_result = VadResult::default();
+        self.vad_delay.clear();
+        self.vad_delay.resize(VAD_DELAY_BLOCKS, false);
+        if let Some(agc) = self.agc.as_mut() {
+            *agc = DtlnAgc::new(agc.config());
+        }
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.reset();
+        }
+        self.stage_pending.clear();
+        self.stage_output.clear();
+        self.stage_output.resize(STAGE_PRIMING, 0.0);
+    }
+
+    /**
+     * Voice activity detected during the most recent denoise() call: the
+     * highest speech probability of its blocks, and whether any was speech.
+     */
+    pub fn vad_result(&self) -> VadResult {
+        self.vad_result
+    }
+
+    /**
+     * denoise() with the output written over the input. Only whole
+     * DTLN_BLOCK_SHIFT blocks are denoised.
+     */
+    pub fn denoise_in_place(&mut self, samples: &mut [f32]) {
+        let mut input = std::mem::take(&mut self.scratch);
+        input.clear();
+        input.extend_from_slice(samples);
+        self.denoise(&input, samples);
+        self.scratch = input;
+    }
+
+    /**
+     * Install an observer which receives the spectra of every processed
+     * block, or remove it with None.
+     */
+    pub fn set_observer(&mut self, observer: Option<Box<dyn BlockObserver>>) {
+        self.observer = observer;
     }
 
     pub fn denoise(&mut self, samples: &[f32], out: &mut [f32]) {
         let sample_count = samples.len();
         let num_blocks = sample_count / DTLN_BLOCK_SHIFT;
         assert!(out.len() >= sample_count);
+        self.vad_result = VadResult::default();
 
         for idx in 0..num_blocks {
             // Shift in_buffer left by DTLN_BLOCK_SHIFT samples
@@ -139,145 +529,206 @@ impl DtlnEngine {
             // Copy next DTLN_BLOCK_SHIFT samples into in_buffer
             self.in_buffer[(DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT)..]
                 .copy_from_slice(&samples[idx * DTLN_BLOCK_SHIFT..(idx + 1) * DTLN_BLOCK_SHIFT]);
+            self.pre_stages
+                .process(&mut self.in_buffer[(DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT)..]);
 
-            self.infer();
+            let block_vad = self.infer();
 
-            // Copy DTLN_BLOCK_SHIFT samples from out_buffer to out
-            out[idx * DTLN_BLOCK_SHIFT..(idx + 1) * DTLN_BLOCK_SHIFT]
-                .copy_from_slice(&self.out_buffer[..DTLN_BLOCK_SHIFT]);
+            // Copy DTLN_BLOCK_SHIFT samples from out_buffer to out, mixed with
+            // the matching input samples which are at the start of in_buffer.
+            let out_block = &mut out[idx * DTLN_BLOCK_SHIFT..(idx + 1) * DTLN_BLOCK_SHIFT];
+            for (i, sample) in out_block.iter_mut().enumerate() {
+                let wet = self.mix.advance();
+                *sample = wet * self.out_buffer[i] + (1.0 - wet) * self.in_buffer[i];
+            }
+
+            // The output block entered the model VAD_DELAY_BLOCKS ago.
+            self.vad_delay.push_back(block_vad.is_speech);
+            let out_is_speech = self.vad_delay.pop_front().unwrap_or(false);
+            if let Some(agc) = self.agc.as_mut() {
+                agc.process_block(out_block, out_is_speech);
+            }
+            if let Some(limiter) = self.limiter.as_mut() {
+                limiter.process(out_block);
+            }
+            self.clipped_samples += out_block.iter().filter(|s| s.abs() > 1.0).count() as u64;
         }
     }
 
-    fn infer(&mut self) {
+    // Process the block in in_buffer, returning its voice activity.
+    fn infer(&mut self) -> VadResult {
         if !self.valid {
             eprintln!("[DTLN] Engine not initialized");
-            return;
+            return VadResult::default();
         }
 
         let mut in_mag = [0f32; DTLN_FFT_OUT_SIZE];
         let mut in_phase = [0f32; DTLN_FFT_OUT_SIZE];
         let mut estimated_block = [0f32; DTLN_BLOCK_LEN];
 
-        // Prepare FFT input
         let mut planner = RealFftPlanner::<f32>::new();
-        let r2c = planner.plan_fft_forward(DTLN_BLOCK_LEN);
-        let mut fft_in = self.in_buffer.to_vec();
-        let mut fft_spectrum = r2c.make_output_vec();
-
-        // Perform real-to-complex FFT
-        r2c.process(&mut fft_in, &mut fft_spectrum).unwrap();
+        analyze_block(&mut planner, &self.in_buffer, &mut in_mag, &mut in_phase);
 
-        // Generate magnitude and phase
-        for i in 0..DTLN_FFT_OUT_SIZE {
-            in_mag[i] = fft_spectrum[i].norm();
-            in_phase[i] = fft_spectrum[i].arg();
+        // Run model 1
+        let mut out_mask = [0f32; DTLN_FFT_OUT_SIZE];
+        if self
+            .backend
+            .run_stage_1(&in_mag, &mut self.states_1, &mut out_mask)
+            .is_err()
+        {
+            eprintln!("[DTLN] Failed to invoke interpreter for model 1");
+            return VadResult::default();
         }
 
-        // Prepare inputs for model 1
-        let in_mag_ptr = unsafe { TfLiteTensorData(self.details1[0]) as *mut f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(in_mag.as_ptr(), in_mag_ptr, DTLN_FFT_OUT_SIZE);
+        let block_vad = self.vad.process_block(&in_mag, &out_mask);
+        self.vad_result = self.vad_result.merge(block_vad);
+
+        let mask_floor = self.mask_floor.advance();
+        for mask in out_mask.iter_mut() {
+            *mask = mask.max(mask_floor);
         }
-        let states1_ptr = unsafe { TfLiteTensorData(self.details1[1]) as *mut f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(self.states_1.as_ptr(), states1_ptr, DTLN_BLOCK_LEN);
+
+        if let Some(observer) = self.observer.as_mut() {
+            let mut noise_estimate = [0f32; DTLN_FFT_OUT_SIZE];
+            for (i, noise) in noise_estimate.iter_mut().enumerate() {
+                *noise = in_mag[i] * (1.0 - out_mask[i]);
+            }
+            observer.on_block(&BlockSpectra {
+                in_mag: &in_mag,
+                out_mask: &out_mask,
+                noise_estimate: &noise_estimate,
+            });
         }
 
-        // Invoke model 1
-        if unsafe { TfLiteInterpreterInvoke(self.interpreter_1) }
-            .to_result()
+        synthesize_block(
+            &mut planner,
+            &in_mag,
+            &in_phase,
+            &out_mask,
+            &mut estimated_block,
+        );
+
+        // Run model 2
+        let mut out_block = [0f32; DTLN_BLOCK_LEN];
+        if self
+            .backend
+            .run_stage_2(&estimated_block, &mut self.states_2, &mut out_block)
             .is_err()
         {
-            eprintln!("[DTLN] Failed to invoke interpreter for model 1");
-            return;
+            eprintln!("[DTLN] Failed to invoke interpreter for model 2");
+            return block_vad;
         }
 
-        // Get outputs
-        let out_mask_ptr = unsafe { TfLiteTensorData(self.output_details_1[0]) as *const f32 };
-        let out_mask = unsafe { slice::from_raw_parts(out_mask_ptr, DTLN_FFT_OUT_SIZE) };
+        overlap_add(&mut self.out_buffer, &out_block);
+        block_vad
+    }
+}
 
-        let out_states1_ptr = unsafe { TfLiteTensorData(self.output_details_1[1]) as *const f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(out_states1_ptr, self.states_1.as_mut_ptr(), DTLN_BLOCK_LEN);
+impl AudioStage for DtlnEngine {
+    // Samples are buffered until they fill a whole DTLN_BLOCK_SHIFT block, so
+    // calls may have any length, at STAGE_PRIMING samples of extra latency.
+    fn process(&mut self, samples: &mut [f32]) {
+        let mut pending = std::mem::take(&mut self.stage_pending);
+        pending.extend_from_slice(samples);
+        let whole = pending.len() / DTLN_BLOCK_SHIFT * DTLN_BLOCK_SHIFT;
+        if whole > 0 {
+            self.denoise_in_place(&mut pending[..whole]);
+            self.stage_output.extend(pending.drain(..whole));
         }
+        self.stage_pending = pending;
 
-        // Apply mask and reconstruct complex spectrum
-        for i in 0..DTLN_FFT_OUT_SIZE {
-            let magnitude = in_mag[i] * out_mask[i];
-            let phase = in_phase[i];
-            let real = magnitude * phase.cos();
-            let imag = magnitude * phase.sin();
-            fft_spectrum[i] = Complex::new(real, imag);
+        for sample in samples.iter_mut() {
+            // The queue always holds at least STAGE_PRIMING minus the pending
+            // samples.
+            *sample = self.stage_output.pop_front().unwrap_or(0.0);
         }
+    }
 
-        // Handle DC component (i = 0)
-        let magnitude = in_mag[0] * out_mask[0];
-        fft_spectrum[0] = Complex::new(magnitude, 0.0);
-
-        // Handle Nyquist component (i = N/2)
-        let magnitude = in_mag[DTLN_FFT_OUT_SIZE - 1] * out_mask[DTLN_FFT_OUT_SIZE - 1];
-        fft_spectrum[DTLN_FFT_OUT_SIZE - 1] = Complex::new(magnitude, 0.0);
-
-        // Prepare for inverse FFT
-        let c2r = planner.plan_fft_inverse(DTLN_BLOCK_LEN);
-        let mut ifft_output = c2r.make_output_vec();
+    fn latency(&self) -> usize {
+        DtlnEngine::latency(self) + STAGE_PRIMING
+    }
 
-        // Perform complex-to-real IFFT
-        c2r.process(&mut fft_spectrum, &mut ifft_output).unwrap();
+    fn reset(&mut self) {
+        DtlnEngine::reset(self);
+    }
+}
 
-        // Normalize the IFFT output
-        for i in 0..DTLN_BLOCK_LEN {
-            estimated_block[i] = ifft_output[i] / DTLN_BLOCK_LEN as f32;
-        }
+/**
+ * Compute the magnitude and phase spectrum of one DTLN_BLOCK_LEN block.
+ */
+pub(crate) fn analyze_block(
+    planner: &mut RealFftPlanner<f32>,
+    in_buffer: &[f32],
+    in_mag: &mut [f32],
+    in_phase: &mut [f32],
+) {
+    // Prepare FFT input
+    let r2c = planner.plan_fft_forward(DTLN_BLOCK_LEN);
+    let mut fft_in = in_buffer.to_vec();
+    let mut fft_spectrum = r2c.make_output_vec();
+
+    // Perform real-to-complex FFT
+    r2c.process(&mut fft_in, &mut fft_spectrum).unwrap();
+
+    // Generate magnitude and phase
+    for i in 0..DTLN_FFT_OUT_SIZE {
+        in_mag[i] = fft_spectrum[i].norm();
+        in_phase[i] = fft_spectrum[i].arg();
+    }
+}
 
-        // Prepare inputs for model 2
-        let est_block_ptr = unsafe { TfLiteTensorData(self.details2[0]) as *mut f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(estimated_block.as_ptr(), est_block_ptr, DTLN_BLOCK_LEN);
-        }
-        let states2_ptr = unsafe { TfLiteTensorData(self.details2[1]) as *mut f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(self.states_2.as_ptr(), states2_ptr, DTLN_BLOCK_LEN);
-        }
+/**
+ * Apply the model 1 mask to a spectrum and transform it back to a
+ * DTLN_BLOCK_LEN time domain block.
+ */
+pub(crate) fn synthesize_block(
+    planner: &mut RealFftPlanner<f32>,
+    in_mag: &[f32],
+    in_phase: &[f32],
+    out_mask: &[f32],
+    estimated_block: &mut [f32],
+) {
+    let c2r = planner.plan_fft_inverse(DTLN_BLOCK_LEN);
+    let mut fft_spectrum = c2r.make_input_vec();
+
+    // Apply mask and reconstruct complex spectrum
+    for i in 0..DTLN_FFT_OUT_SIZE {
+        let magnitude = in_mag[i] * out_mask[i];
+        let phase = in_phase[i];
+        let real = magnitude * phase.cos();
+        let imag = magnitude * phase.sin();
+        fft_spectrum[i] = Complex::new(real, imag);
+    }
 
-        // Invoke model 2
-        if unsafe { TfLiteInterpreterInvoke(self.interpreter_2) }
-            .to_result()
-            .is_err()
-        {
-            eprintln!("[DTLN] Failed to invoke interpreter for model 2");
-            return;
-        }
+    // Handle DC component (i = 0)
+    let magnitude = in_mag[0] * out_mask[0];
+    fft_spectrum[0] = Complex::new(magnitude, 0.0);
 
-        // Get outputs
-        let out_block_ptr = unsafe { TfLiteTensorData(self.output_details_2[0]) as *const f32 };
-        let out_block = unsafe { slice::from_raw_parts(out_block_ptr, DTLN_BLOCK_LEN) };
+    // Handle Nyquist component (i = N/2)
+    let magnitude = in_mag[DTLN_FFT_OUT_SIZE - 1] * out_mask[DTLN_FFT_OUT_SIZE - 1];
+    fft_spectrum[DTLN_FFT_OUT_SIZE - 1] = Complex::new(magnitude, 0.0);
 
-        let out_states2_ptr = unsafe { TfLiteTensorData(self.output_details_2[1]) as *const f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(out_states2_ptr, self.states_2.as_mut_ptr(), DTLN_BLOCK_LEN);
-        }
+    // Prepare for inverse FFT
+    let mut ifft_output = c2r.make_output_vec();
 
-        // Overlap-add
-        self.out_buffer.copy_within(DTLN_BLOCK_SHIFT.., 0);
-        for i in (DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT)..DTLN_BLOCK_LEN {
-            self.out_buffer[i] = 0.0;
-        }
+    // Perform complex-to-real IFFT
+    c2r.process(&mut fft_spectrum, &mut ifft_output).unwrap();
 
-        for (i, item) in out_block.iter().enumerate().take(DTLN_BLOCK_LEN) {
-            self.out_buffer[i] += item;
-        }
+    // Normalize the IFFT output
+    for i in 0..DTLN_BLOCK_LEN {
+        estimated_block[i] = ifft_output[i] / DTLN_BLOCK_LEN as f32;
     }
 }
 
-impl Drop for DtlnEngine {
-    fn drop(&mut self) {
-        unsafe {
-            TfLiteInterpreterDelete(self.interpreter_1);
-            TfLiteInterpreterDelete(self.interpreter_2);
-            TfLiteModelDelete(self.model1);
-            TfLiteModelDelete(self.model2);
-        }
+/**
+ * Shift out_buffer by DTLN_BLOCK_SHIFT samples and overlap-add out_block.
+ */
+pub(crate) fn overlap_add(out_buffer: &mut [f32], out_block: &[f32]) {
+    out_buffer.copy_within(DTLN_BLOCK_SHIFT.., 0);
+    out_buffer[(DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT)..].fill(0.0);
+
+    for (i, item) in out_block.iter().enumerate().take(DTLN_BLOCK_LEN) {
+        out_buffer[i] += item;
     }
 }
 
@@ -297,3 +748,184 @@ pub fn dtln_denoise(engine: &mut DtlnEngine, samples: &[f32], out: &mut [f32]) -
     engine.denoise(samples, out);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_utilities::{read_wav_to_pcm32, write_pcm16_to_wav};
+
+    const GOLDEN_CLIPS: [&str; 4] = [
+        "airconditioning",
+        "dog_barking_noisy",
+        "restaurant_noisy",
+        "trump_vs_helicopter",
+    ];
+
+    // Goldens are 16 bit, so even the native backend which produced them
+    // differs by up to half a quantization step.
+    const NATIVE_TOLERANCE: f32 = 1e-4;
+    // Same bound as test_native_matches_tflite.
+    const TFLITE_TOLERANCE: f32 = 1e-3;
+
+    fn update_golden() -> bool {
+        std::env::var("DTLN_UPDATE_GOLDEN").is_ok_and(|v| v != "0")
+    }
+
+    /**
+     * Denoise every clip in clips/ and compare against the reference output
+     * in clips/golden/, or write the output as the new reference if update
+     * is set.
+     */
+    fn check_golden_outputs(
+        mut create: impl FnMut() -> Option<DtlnEngine>,
+        tolerance: f32,
+        update: bool,
+    ) {
+        let dir = env!("CARGO_MANIFEST_DIR");
+
+        for clip in GOLDEN_CLIPS {
+            let mut samples = vec![];
+            read_wav_to_pcm32(&format!("{}/clips/{}.wav", dir, clip), &mut samples).unwrap();
+
+            let mut engine = create().unwrap();
+            let mut out = vec![0.0; samples.len()];
+            engine.denoise(&samples, &mut out);
+
+            let golden_path = format!("{}/clips/golden/{}.wav", dir, clip);
+            if update {
+                write_pcm16_to_wav(&out, &golden_path, 16000).unwrap();
+                continue;
+            }
+
+            let mut golden = vec![];
+            read_wav_to_pcm32(&golden_path, &mut golden).unwrap();
+            assert_eq!(golden.len(), out.len(), "{}: length changed", clip);
+
+            let (index, diff) = out
+                .iter()
+                .zip(&golden)
+                .map(|(out, golden)| (out - golden).abs())
+                .enumerate()
+                .fold(
+                    (0, 0.0),
+                    |max, (i, diff)| if diff > max.1 { (i, diff) } else { max },
+                );
+            assert!(
+                diff < tolerance,
+                "{}: sample {} differs from the golden output by {}",
+                clip,
+                index,
+                diff
+            );
+        }
+    }
+
+    // The references are produced by the native backend, which is
+    // deterministic and runs without TFLite. Run with DTLN_UPDATE_GOLDEN=1 to
+    // regenerate them after an intended change to the output.
+    #[test]
+    fn test_golden_outputs_native() {
+        check_golden_outputs(DtlnEngine::new_native, NATIVE_TOLERANCE, update_golden());
+    }
+
+    #[test]
+    fn test_golden_outputs_tflite() {
+        // Never compare against references which are being rewritten.
+        if update_golden() {
+            return;
+        }
+        check_golden_outputs(DtlnEngine::new, TFLITE_TOLERANCE, false);
+    }
+
+    #[test]
+    fn test_mix_and_mask_floor_ramps() {
+        let samples = (0..4096)
+            .map(|i| (i as f32 * 0.03).sin() * 0.3 + ((i * 7919) % 97) as f32 / 970.0)
+            .collect::<Vec<f32>>();
+        let mut out = vec![0.0; samples.len()];
+
+        // Once the ramp is done, mix 0.0 outputs the input delayed by latency().
+        let mut engine = DtlnEngine::new_native().unwrap();
+        engine.set_mix(0.0);
+        engine.denoise(&samples, &mut out);
+        let latency = engine.latency();
+        let ramp_len = MIX_RAMP_SAMPLES as usize;
+        assert_ne!(out[ramp_len - 2], samples[ramp_len - 2 - latency]);
+        assert_eq!(
+            out[ramp_len - 1..],
+            samples[ramp_len - 1 - latency..samples.len() - latency]
+        );
+
+        // Removing the mask floor takes MASK_FLOOR_RAMP_BLOCKS blocks.
+        let mut engine = DtlnEngine::new_native().unwrap();
+        engine.set_min_gain_db(-12.0);
+        engine.reset();
+        engine.set_min_gain_db(f32::NEG_INFINITY);
+        for _ in 1..MASK_FLOOR_RAMP_BLOCKS {
+            engine.denoise(&samples[..DTLN_BLOCK_SHIFT], &mut out);
+            assert!(engine.mask_floor.value() > 0.0);
+        }
+        engine.denoise(&samples[..DTLN_BLOCK_SHIFT], &mut out);
+        assert_eq!(engine.mask_floor.value(), 0.0);
+
+        // min_gain_db -inf is the unfloored output.
+        let mut expected = vec![0.0; samples.len()];
+        DtlnEngine::new_native()
+            .unwrap()
+            .denoise(&samples, &mut expected);
+        engine.reset();
+        engine.denoise(&samples, &mut out);
+        assert_eq!(out, expected);
+
+        // NaN leaves the settings unchanged.
+        engine.set_min_gain_db(f32::NAN);
+        engine.set_mix(f32::NAN);
+        assert_eq!(engine.mask_floor.target(), 0.0);
+        assert_eq!(engine.mix.target(), 1.0);
+    }
+
+    #[test]
+    fn test_stage_buffers_partial_blocks() {
+        let samples = (0..4096)
+            .map(|i| (i as f32 * 0.03).sin() * 0.3)
+            .collect::<Vec<f32>>();
+        let mut expected = vec![0.0; samples.len()];
+        DtlnEngine::new_native()
+            .unwrap()
+            .denoise(&samples, &mut expected);
+
+        let mut engine = DtlnEngine::new_native().unwrap();
+        let extra = AudioStage::latency(&engine) - engine.latency();
+        let mut out = samples.clone();
+        let mut offset = 0;
+        for chunk in [1, 100, 128, 300, 77].iter().cycle() {
+            let end = (offset + chunk).min(out.len());
+            AudioStage::process(&mut engine, &mut out[offset..end]);
+            offset = end;
+            if offset == out.len() {
+                break;
+            }
+        }
+
+        assert!(out[..extra].iter().all(|s| *s == 0.0));
+        assert_eq!(out[extra..], expected[..expected.len() - extra]);
+    }
+
+    #[test]
+    fn test_reset_matches_new_engine() {
+        let samples = (0..4096)
+            .map(|i| (i as f32 * 0.03).sin() * 0.3 + ((i * 7919) % 97) as f32 / 970.0)
+            .collect::<Vec<f32>>();
+        let mut expected = vec![0.0; samples.len()];
+        DtlnEngine::new_native()
+            .unwrap()
+            .denoise(&samples, &mut expected);
+
+        let mut engine = DtlnEngine::new_native().unwrap();
+        let mut out = vec![0.0; samples.len()];
+        engine.denoise(&samples, &mut out);
+        engine.reset();
+        engine.denoise(&samples, &mut out);
+        assert_eq!(out, expected);
+    }
+}
@@ -0,0 +1,153 @@
+// Pre-filters removing DC offset and low frequency rumble, which cheap
+// microphones add and which would otherwise reach the model.
+
+use crate::audio_stage::{AudioStage, StageChain};
+
+// DTLN sample rate.
+const SAMPLE_RATE: f64 = 16000.0;
+
+// Pole radius of the DC blocker, a corner of ~8 Hz at 16khz.
+const DC_BLOCKER_POLE: f64 = 0.995;
+
+// Butterworth response for the high-pass biquad.
+const HIGH_PASS_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PreFilterConfig {
+    // Remove DC offset.
+    pub dc_blocker: bool,
+    // Cutoff of a second order high-pass filter, None disables it.
+    pub high_pass_hz: Option<f32>,
+}
+
+impl PreFilterConfig {
+    /**
+     * Build the stages of this configuration, DC blocker first.
+     */
+    pub fn build(&self) -> StageChain {
+        let mut chain = StageChain::new();
+        if self.dc_blocker {
+            chain.push(Box::new(DcBlocker::new()));
+        }
+        if let Some(cutoff_hz) = self.high_pass_hz {
+            chain.push(Box::new(Biquad::high_pass(cutoff_hz, HIGH_PASS_Q as f32)));
+        }
+        chain
+    }
+}
+
+/**
+ * First order DC blocker, y[n] = x[n] - x[n-1] + R * y[n-1].
+ */
+#[derive(Default)]
+pub struct DcBlocker {
+    previous_input: f64,
+    previous_output: f64,
+}
+
+impl DcBlocker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AudioStage for DcBlocker {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let input = *sample as f64;
+            let output = input - self.previous_input + DC_BLOCKER_POLE * self.previous_output;
+            self.previous_input = input;
+            self.previous_output = output;
+            *sample = output as f32;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/**
+ * Second order IIR filter in transposed direct form II. State is kept in f64,
+ * as low cutoffs at 16khz put the poles close to the unit circle.
+ */
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /**
+     * High-pass filter from the Audio EQ Cookbook.
+     * @param cutoff_hz -3 dB frequency for a Q of 1/sqrt(2).
+     */
+    pub fn high_pass(cutoff_hz: f32, q: f32) -> Self {
+        let w0 =
+            2.0 * std::f64::consts::PI * (cutoff_hz as f64).clamp(1.0, SAMPLE_RATE / 2.0 - 1.0)
+                / SAMPLE_RATE;
+        let alpha = w0.sin() / (2.0 * q as f64);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b0: (1.0 + cos) / 2.0 / a0,
+            b1: -(1.0 + cos) / a0,
+            b2: (1.0 + cos) / 2.0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+}
+
+impl AudioStage for Biquad {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let input = *sample as f64;
+            let output = self.b0 * input + self.z1;
+            self.z1 = self.b1 * input - self.a1 * output + self.z2;
+            self.z2 = self.b2 * input - self.a2 * output;
+            *sample = output as f32;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone_rms(config: PreFilterConfig, frequency: f32, offset: f32) -> f32 {
+        let mut samples = (0..32000)
+            .map(|i| offset + (2.0 * std::f32::consts::PI * frequency * i as f32 / 16000.0).sin())
+            .collect::<Vec<f32>>();
+        config.build().process(&mut samples);
+
+        // Skip the settling time of the filters.
+        let tail = &samples[16000..];
+        (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_pre_filters() {
+        let config = PreFilterConfig {
+            dc_blocker: true,
+            high_pass_hz: Some(100.0),
+        };
+        let unity = std::f32::consts::FRAC_1_SQRT_2;
+
+        // DC is removed and speech frequencies pass.
+        assert!((tone_rms(config, 1000.0, 0.5) - unity).abs() < 0.01);
+        // Mains hum an octave below the cutoff is attenuated by ~12 dB.
+        assert!(tone_rms(config, 50.0, 0.0) < unity / 3.5);
+    }
+}
@@ -0,0 +1,230 @@
+// Look-ahead peak limiter, keeping the output below a ceiling before it is
+// converted to integer samples.
+//
+// For every sample the gain needed to stay below the ceiling is computed. The
+// minimum of these gains over the look-ahead window, averaged over the same
+// window, gives a smooth gain which has fully reached the required value once
+// the matching sample leaves the delay line, so the ceiling is never exceeded.
+// Gain increases are further slowed down by the release time.
+
+use std::collections::VecDeque;
+
+use serde::Deserialize;
+
+use crate::audio_stage::AudioStage;
+
+// DTLN sample rate.
+const SAMPLE_RATE: f32 = 16000.0;
+
+// Part of the C API in dtln_capi.rs, hence the C layout.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct LimiterConfig {
+    // Highest output level in dB, relative to a sample value of 1.0.
+    pub ceiling_db: f32,
+    // How far ahead peaks are detected. Adds this much latency.
+    pub lookahead_ms: f32,
+    // Time for the gain to recover after a peak.
+    pub release_ms: f32,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        LimiterConfig {
+            ceiling_db: -1.0,
+            lookahead_ms: 5.0,
+            release_ms: 50.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClipStats {
+    // Output samples beyond +-1.0, which clip on integer conversion.
+    pub clipped_samples: u64,
+    // Output samples attenuated by the limiter.
+    pub limited_samples: u64,
+}
+
+pub struct DtlnLimiter {
+    config: LimiterConfig,
+    ceiling: f32,
+    release: f32,
+    // Input samples waiting to be output.
+    delay: VecDeque<f32>,
+    // Candidates for the minimum required gain in the window, as
+    // (sample index, gain) with increasing gains.
+    window_min: VecDeque<(u64, f32)>,
+    // The last window minimums, and their sum for the moving average.
+    averaged: VecDeque<f32>,
+    averaged_sum: f64,
+    gain: f32,
+    sample_index: u64,
+    limited_samples: u64,
+}
+
+impl DtlnLimiter {
+    pub fn new(config: LimiterConfig) -> Self {
+        let lookahead = Self::lookahead_samples(&config);
+        let mut limiter = DtlnLimiter {
+            config,
+            ceiling: 1.0,
+            release: 0.0,
+            delay: VecDeque::from(vec![0.0; lookahead]),
+            window_min: VecDeque::new(),
+            averaged: VecDeque::from(vec![1.0; lookahead + 1]),
+            averaged_sum: (lookahead + 1) as f64,
+            gain: 1.0,
+            sample_index: 0,
+            limited_samples: 0,
+        };
+        limiter.set_config(config);
+        limiter
+    }
+
+    pub fn config(&self) -> LimiterConfig {
+        self.config
+    }
+
+    /**
+     * Change the ceiling and release time. The look-ahead is fixed when the
+     * limiter is created.
+     */
+    pub fn set_config(&mut self, config: LimiterConfig) {
+        self.config.ceiling_db = config.ceiling_db;
+        self.config.release_ms = config.release_ms;
+        self.ceiling = 10f32.powf(config.ceiling_db.min(0.0) / 20.0);
+        let release_samples = config.release_ms.max(0.0) * SAMPLE_RATE / 1000.0;
+        self.release = if release_samples > 0.0 {
+            (-1.0 / release_samples).exp()
+        } else {
+            0.0
+        };
+    }
+
+    /**
+     * Latency added by the limiter, in samples.
+     */
+    pub fn latency(&self) -> usize {
+        self.delay.len()
+    }
+
+    /**
+     * Output samples attenuated so far.
+     */
+    pub fn limited_samples(&self) -> u64 {
+        self.limited_samples
+    }
+
+    pub fn reset_limited_samples(&mut self) {
+        self.limited_samples = 0;
+    }
+
+    /**
+     * Clear the delay line and release the gain. limited_samples() is kept.
+     */
+    pub fn reset(&mut self) {
+        let limited_samples = self.limited_samples;
+        *self = DtlnLimiter::new(self.config);
+        self.limited_samples = limited_samples;
+    }
+
+    /**
+     * Limit samples in place. The output is delayed by latency() samples.
+     */
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let window = self.averaged.len() as u64;
+        for sample in samples.iter_mut() {
+            let required = if sample.abs() > self.ceiling {
+                self.ceiling / sample.abs()
+            } else {
+                1.0
+            };
+
+            // Sliding minimum of the required gain over the window.
+            while self
+                .window_min
+                .back()
+                .is_some_and(|(_, gain)| *gain >= required)
+            {
+                self.window_min.pop_back();
+            }
+            self.window_min.push_back((self.sample_index, required));
+            while self
+                .window_min
+                .front()
+                .is_some_and(|(index, _)| index + window <= self.sample_index)
+            {
+                self.window_min.pop_front();
+            }
+            let minimum = self.window_min.front().map_or(1.0, |(_, gain)| *gain);
+
+            // Moving average of the minimum, which ramps down in time.
+            self.averaged_sum += minimum as f64 - self.averaged.pop_front().unwrap() as f64;
+            self.averaged.push_back(minimum);
+            let smoothed = (self.averaged_sum / window as f64).min(1.0) as f32;
+
+            self.gain = if smoothed < self.gain {
+                smoothed
+            } else {
+                smoothed + (self.gain - smoothed) * self.release
+            };
+
+            self.delay.push_back(*sample);
+            let delayed = self.delay.pop_front().unwrap();
+            if self.gain < 1.0 && delayed != 0.0 {
+                self.limited_samples += 1;
+            }
+            // The averaged gain can exceed the required one by rounding.
+            *sample = (delayed * self.gain).clamp(-self.ceiling, self.ceiling);
+            self.sample_index += 1;
+        }
+    }
+
+    fn lookahead_samples(config: &LimiterConfig) -> usize {
+        (config.lookahead_ms.max(0.0) * SAMPLE_RATE / 1000.0).round() as usize
+    }
+}
+
+impl AudioStage for DtlnLimiter {
+    fn process(&mut self, samples: &mut [f32]) {
+        DtlnLimiter::process(self, samples);
+    }
+
+    fn latency(&self) -> usize {
+        DtlnLimiter::latency(self)
+    }
+
+    fn reset(&mut self) {
+        DtlnLimiter::reset(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter_holds_ceiling() {
+        let mut limiter = DtlnLimiter::new(LimiterConfig::default());
+        let ceiling = 10f32.powf(-1.0 / 20.0);
+
+        let input = (0..16000)
+            .map(|i| {
+                let burst = if (4000..6000).contains(&i) { 3.0 } else { 0.5 };
+                burst * (i as f32 * 0.1).sin()
+            })
+            .collect::<Vec<f32>>();
+        let mut output = input.clone();
+        limiter.process(&mut output);
+
+        let latency = limiter.latency();
+        assert!(output.iter().all(|s| s.abs() <= ceiling));
+        // Quiet material before the burst passes unchanged, only delayed.
+        for i in latency..3000 {
+            assert!((output[i] - input[i - latency]).abs() < 1e-6);
+        }
+        assert!(limiter.limited_samples() > 2000);
+    }
+}
@@ -0,0 +1,288 @@
+// Objective quality metrics, comparing processed audio against a clean
+// reference. Both signals must be 16khz and time aligned, such as the output
+// of denoise_offline().
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::constants::*;
+use crate::dtln_spectrogram::stft_magnitudes;
+
+// DTLN sample rate.
+const SAMPLE_RATE: f32 = 16000.0;
+
+// Keeps ratios finite when a signal is silent or reproduced exactly.
+const EPSILON: f64 = 1e-10;
+
+// Per frame SNR limits of the segmental SNR.
+const SEGMENTAL_SNR_MIN_DB: f64 = -10.0;
+const SEGMENTAL_SNR_MAX_DB: f64 = 35.0;
+
+// Intelligibility measure, following STOI (Taal et al., 2011) on the engine's
+// STFT framing instead of resampling to 10khz: one-third octave bands from
+// 150 Hz, envelopes correlated over ~384 ms segments, frames more than 40 dB
+// below the loudest reference frame dropped, and processed envelopes clipped
+// at 15 dB below the reference.
+const STOI_BAND_COUNT: usize = 15;
+const STOI_LOWEST_CENTER_HZ: f32 = 150.0;
+const STOI_SEGMENT_FRAMES: usize = 48;
+const STOI_DYNAMIC_RANGE_DB: f32 = 40.0;
+const STOI_CLIP_DB: f32 = -15.0;
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct QualityMetrics {
+    // Signal to noise ratio in dB.
+    pub snr: f64,
+    // Scale-invariant signal to distortion ratio in dB.
+    pub si_sdr: f64,
+    // Mean per frame SNR in dB, each frame limited to -10..35 dB.
+    pub segmental_snr: f64,
+    // Mean log-spectral distance in dB, lower is better.
+    pub log_spectral_distance: f64,
+    // STOI-style short-time intelligibility, from 0 to 1.
+    pub intelligibility: f64,
+}
+
+impl QualityMetrics {
+    /**
+     * The metrics as a JSON object. Non-finite values are written as null.
+     */
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/**
+ * Compute every metric of processed audio against the clean reference.
+ * If the lengths differ, the longer signal is truncated.
+ */
+pub fn evaluate(reference: &[f32], processed: &[f32]) -> Result<QualityMetrics> {
+    let len = reference.len().min(processed.len());
+    let (reference, processed) = (&reference[..len], &processed[..len]);
+    if len < DTLN_BLOCK_LEN + (STOI_SEGMENT_FRAMES - 1) * DTLN_BLOCK_SHIFT {
+        return Err(anyhow!(
+            "At least {:.2}s of audio is needed for evaluation",
+            (DTLN_BLOCK_LEN + (STOI_SEGMENT_FRAMES - 1) * DTLN_BLOCK_SHIFT) as f32 / SAMPLE_RATE
+        ));
+    }
+
+    let reference_spectra = stft_magnitudes(reference);
+    let processed_spectra = stft_magnitudes(processed);
+
+    Ok(QualityMetrics {
+        snr: snr(reference, processed),
+        si_sdr: si_sdr(reference, processed),
+        segmental_snr: segmental_snr(reference, processed),
+        log_spectral_distance: log_spectral_distance(&reference_spectra, &processed_spectra),
+        intelligibility: intelligibility(&reference_spectra, &processed_spectra),
+    })
+}
+
+pub fn snr(reference: &[f32], processed: &[f32]) -> f64 {
+    let mut signal = 0.0;
+    let mut noise = 0.0;
+    for (r, p) in reference.iter().zip(processed) {
+        signal += (*r as f64).powi(2);
+        noise += (*r as f64 - *p as f64).powi(2);
+    }
+    ratio_db(signal, noise)
+}
+
+pub fn si_sdr(reference: &[f32], processed: &[f32]) -> f64 {
+    let reference_mean = mean(reference);
+    let processed_mean = mean(processed);
+
+    let mut dot = 0.0;
+    let mut reference_energy = 0.0;
+    for (r, p) in reference.iter().zip(processed) {
+        dot += (*r as f64 - reference_mean) * (*p as f64 - processed_mean);
+        reference_energy += (*r as f64 - reference_mean).powi(2);
+    }
+    let scale = dot / (reference_energy + EPSILON);
+
+    let mut target = 0.0;
+    let mut distortion = 0.0;
+    for (r, p) in reference.iter().zip(processed) {
+        let projection = scale * (*r as f64 - reference_mean);
+        target += projection.powi(2);
+        distortion += (*p as f64 - processed_mean - projection).powi(2);
+    }
+    ratio_db(target, distortion)
+}
+
+pub fn segmental_snr(reference: &[f32], processed: &[f32]) -> f64 {
+    let frames = reference
+        .chunks_exact(DTLN_BLOCK_LEN)
+        .zip(processed.chunks_exact(DTLN_BLOCK_LEN))
+        .map(|(reference, processed)| {
+            snr(reference, processed).clamp(SEGMENTAL_SNR_MIN_DB, SEGMENTAL_SNR_MAX_DB)
+        })
+        .collect::<Vec<f64>>();
+    frames.iter().sum::<f64>() / frames.len().max(1) as f64
+}
+
+/**
+ * Root mean square difference of the log power spectra, averaged over frames.
+ * @param reference_spectra Magnitudes from stft_magnitudes().
+ * @param processed_spectra Magnitudes from stft_magnitudes().
+ */
+pub fn log_spectral_distance(reference_spectra: &[f32], processed_spectra: &[f32]) -> f64 {
+    let frames = reference_spectra
+        .chunks_exact(DTLN_FFT_OUT_SIZE)
+        .zip(processed_spectra.chunks_exact(DTLN_FFT_OUT_SIZE))
+        .map(|(reference, processed)| {
+            let sum = reference
+                .iter()
+                .zip(processed)
+                .map(|(r, p)| {
+                    let r_db = 10.0 * ((*r as f64).powi(2) + EPSILON).log10();
+                    let p_db = 10.0 * ((*p as f64).powi(2) + EPSILON).log10();
+                    (r_db - p_db).powi(2)
+                })
+                .sum::<f64>();
+            (sum / DTLN_FFT_OUT_SIZE as f64).sqrt()
+        })
+        .collect::<Vec<f64>>();
+    frames.iter().sum::<f64>() / frames.len().max(1) as f64
+}
+
+/**
+ * STOI-style intelligibility, see the STOI_ constants.
+ * @param reference_spectra Magnitudes from stft_magnitudes().
+ * @param processed_spectra Magnitudes from stft_magnitudes().
+ */
+pub fn intelligibility(reference_spectra: &[f32], processed_spectra: &[f32]) -> f64 {
+    let bin_hz = SAMPLE_RATE / DTLN_BLOCK_LEN as f32;
+    let bands = (0..STOI_BAND_COUNT)
+        .map(|band| {
+            let center = STOI_LOWEST_CENTER_HZ * 2f32.powf(band as f32 / 3.0);
+            let low = (center * 2f32.powf(-1.0 / 6.0) / bin_hz).round() as usize;
+            let high = (center * 2f32.powf(1.0 / 6.0) / bin_hz).round() as usize;
+            low..high.min(DTLN_FFT_OUT_SIZE)
+        })
+        .collect::<Vec<_>>();
+
+    // Band envelopes of the frames which are not silent in the reference.
+    let frame_energy = |frame: &[f32]| frame.iter().map(|m| m * m).sum::<f32>();
+    let loudest = reference_spectra
+        .chunks_exact(DTLN_FFT_OUT_SIZE)
+        .map(frame_energy)
+        .fold(0.0, f32::max);
+    let threshold = loudest * 10f32.powf(-STOI_DYNAMIC_RANGE_DB / 10.0);
+
+    let mut reference_envelopes = vec![vec![]; STOI_BAND_COUNT];
+    let mut processed_envelopes = vec![vec![]; STOI_BAND_COUNT];
+    for (reference, processed) in reference_spectra
+        .chunks_exact(DTLN_FFT_OUT_SIZE)
+        .zip(processed_spectra.chunks_exact(DTLN_FFT_OUT_SIZE))
+    {
+        if frame_energy(reference) < threshold {
+            continue;
+        }
+        for (band, bins) in bands.iter().enumerate() {
+            reference_envelopes[band].push(frame_energy(&reference[bins.clone()]).sqrt() as f64);
+            processed_envelopes[band].push(frame_energy(&processed[bins.clone()]).sqrt() as f64);
+        }
+    }
+
+    let clip = 1.0 + 10f64.powf(-STOI_CLIP_DB as f64 / 20.0);
+    let mut total = 0.0;
+    let mut count = 0;
+    for (reference, processed) in reference_envelopes.iter().zip(&processed_envelopes) {
+        for end in STOI_SEGMENT_FRAMES..=reference.len() {
+            let x = &reference[end - STOI_SEGMENT_FRAMES..end];
+            let y = &processed[end - STOI_SEGMENT_FRAMES..end];
+
+            // Normalize the processed segment to the reference energy, and clip.
+            let scale = norm(x) / (norm(y) + EPSILON);
+            let y = y
+                .iter()
+                .zip(x)
+                .map(|(y, x)| (y * scale).min(clip * x))
+                .collect::<Vec<f64>>();
+
+            total += correlation(x, &y);
+            count += 1;
+        }
+    }
+    if count == 0 {
+        0.0
+    } else {
+        total / count as f64
+    }
+}
+
+fn ratio_db(signal: f64, noise: f64) -> f64 {
+    10.0 * ((signal + EPSILON) / (noise + EPSILON)).log10()
+}
+
+fn mean(samples: &[f32]) -> f64 {
+    samples.iter().map(|s| *s as f64).sum::<f64>() / samples.len().max(1) as f64
+}
+
+fn norm(values: &[f64]) -> f64 {
+    values.iter().map(|v| v * v).sum::<f64>().sqrt()
+}
+
+fn correlation(x: &[f64], y: &[f64]) -> f64 {
+    let x_mean = x.iter().sum::<f64>() / x.len() as f64;
+    let y_mean = y.iter().sum::<f64>() / y.len() as f64;
+    let mut dot = 0.0;
+    let mut x_energy = 0.0;
+    let mut y_energy = 0.0;
+    for (x, y) in x.iter().zip(y) {
+        dot += (x - x_mean) * (y - y_mean);
+        x_energy += (x - x_mean).powi(2);
+        y_energy += (y - y_mean).powi(2);
+    }
+    dot / ((x_energy * y_energy).sqrt() + EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_rank_degradation() -> Result<()> {
+        let reference = (0..32000)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE;
+                // Amplitude modulated tone, so the envelopes are not flat.
+                (2.0 * std::f32::consts::PI * 440.0 * t).sin()
+                    * (0.6 + 0.4 * (2.0 * std::f32::consts::PI * 3.0 * t).sin())
+            })
+            .collect::<Vec<f32>>();
+        let noisy = reference
+            .iter()
+            .map(|s| s + 0.3 * (rand::random::<f32>() - 0.5))
+            .collect::<Vec<f32>>();
+
+        let exact = evaluate(&reference, &reference)?;
+        let degraded = evaluate(&reference, &noisy)?;
+
+        assert!(exact.si_sdr > 60.0);
+        assert!(exact.log_spectral_distance < 1e-3);
+        assert!(exact.intelligibility > 0.999);
+
+        assert!(degraded.snr < exact.snr);
+        assert!(degraded.si_sdr < exact.si_sdr);
+        assert!(degraded.segmental_snr < exact.segmental_snr);
+        assert!(degraded.log_spectral_distance > exact.log_spectral_distance);
+        assert!(degraded.intelligibility < exact.intelligibility);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics_json_non_finite() -> Result<()> {
+        let metrics = QualityMetrics {
+            snr: f64::NAN,
+            si_sdr: f64::INFINITY,
+            ..QualityMetrics::default()
+        };
+        let json = serde_json::from_str::<serde_json::Value>(&metrics.to_json())?;
+        assert!(json["snr"].is_null());
+        assert!(json["si_sdr"].is_null());
+        assert_eq!(json["intelligibility"], 0.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+// Native Rust implementation of the two DTLN models.
+//
+// The weights are extracted from the bundled TFLite models at construction,
+// and the LSTM, dense and 1D-conv layers are evaluated with the kernels in
+// simd.rs instead of going through the TFLite interpreter.
+
+use anyhow::{anyhow, Result};
+
+use crate::constants::*;
+use crate::simd::{add_assign, matmul, sigmoid};
+use crate::tflite_model::{read_const_tensors, ConstTensor};
+
+// Hidden units of every LSTM layer in both models.
+const LSTM_UNITS: usize = 128;
+
+// Stacked LSTM layers per model.
+const LSTM_LAYERS: usize = 2;
+
+// Size of the learned feature representation in model 2.
+const ENCODER_SIZE: usize = 256;
+
+// Takes constant tensors in the order the model's operators consume them.
+struct TensorReader {
+    tensors: std::vec::IntoIter<ConstTensor>,
+}
+
+impl TensorReader {
+    fn next(&mut self, shape: &[usize]) -> Result<Vec<f32>> {
+        let tensor = self
+            .tensors
+            .next()
+            .ok_or_else(|| anyhow!("Model is missing a tensor of shape {:?}", shape))?;
+        if tensor.shape != shape {
+            return Err(anyhow!(
+                "Tensor {} has shape {:?}, expected {:?}",
+                tensor.name,
+                tensor.shape,
+                shape
+            ));
+        }
+        Ok(tensor.data)
+    }
+}
+
+// y = W x + b, with W stored [out, in] like TFLite FULLY_CONNECTED and
+// kernel size 1 CONV_2D weights.
+struct Dense {
+    weights: Vec<f32>,
+    bias: Vec<f32>,
+    input_size: usize,
+}
+
+impl Dense {
+    fn read(reader: &mut TensorReader, weight_shape: &[usize]) -> Result<Self> {
+        let weights = reader.next(weight_shape)?;
+        let bias = reader.next(&[weight_shape[0]])?;
+        Ok(Dense {
+            input_size: weights.len() / bias.len(),
+            weights,
+            bias,
+        })
+    }
+
+    fn forward(&self, inputs: &[f32], out: &mut [f32]) {
+        matmul(&self.weights, self.input_size, inputs, out);
+        for o in out.chunks_exact_mut(self.bias.len()) {
+            add_assign(o, &self.bias);
+        }
+    }
+}
+
+// Keras LSTM cell with gates ordered input, forget, cell, output.
+struct LstmLayer {
+    kernel: Vec<f32>,
+    recurrent: Vec<f32>,
+    bias: Vec<f32>,
+    input_size: usize,
+    h_prev: Vec<f32>,
+    gates: Vec<f32>,
+    recurrent_gates: Vec<f32>,
+}
+
+impl LstmLayer {
+    fn read(reader: &mut TensorReader, input_size: usize) -> Result<Self> {
+        Ok(LstmLayer {
+            kernel: reader.next(&[4 * LSTM_UNITS, input_size])?,
+            recurrent: reader.next(&[4 * LSTM_UNITS, LSTM_UNITS])?,
+            bias: reader.next(&[4 * LSTM_UNITS])?,
+            input_size,
+            h_prev: vec![],
+            gates: vec![],
+            recurrent_gates: vec![],
+        })
+    }
+
+    /**
+     * Run one time step for every stream in the batch.
+     * @param layer Index of this layer within the model state.
+     * @param states Model states of every stream, [stream][layer][unit][h, c].
+     * @param out The new hidden state h of every stream.
+     */
+    fn step(&mut self, layer: usize, inputs: &[f32], states: &mut [f32], out: &mut [f32]) {
+        let batch = out.len() / LSTM_UNITS;
+        self.h_prev.resize(batch * LSTM_UNITS, 0.0);
+        self.gates.resize(batch * 4 * LSTM_UNITS, 0.0);
+        self.recurrent_gates.resize(batch * 4 * LSTM_UNITS, 0.0);
+
+        let layer_states = states
+            .chunks_exact_mut(DTLN_BLOCK_LEN)
+            .map(|s| &mut s[layer * 2 * LSTM_UNITS..(layer + 1) * 2 * LSTM_UNITS]);
+
+        for (h_prev, state) in self.h_prev.chunks_exact_mut(LSTM_UNITS).zip(layer_states) {
+            for (h, s) in h_prev.iter_mut().zip(state.chunks_exact(2)) {
+                *h = s[0];
+            }
+        }
+
+        matmul(&self.kernel, self.input_size, inputs, &mut self.gates);
+        matmul(
+            &self.recurrent,
+            LSTM_UNITS,
+            &self.h_prev,
+            &mut self.recurrent_gates,
+        );
+        add_assign(&mut self.gates, &self.recurrent_gates);
+
+        let layer_states = states
+            .chunks_exact_mut(DTLN_BLOCK_LEN)
+            .map(|s| &mut s[layer * 2 * LSTM_UNITS..(layer + 1) * 2 * LSTM_UNITS]);
+
+        for ((gates, state), out) in self
+            .gates
+            .chunks_exact_mut(4 * LSTM_UNITS)
+            .zip(layer_states)
+            .zip(out.chunks_exact_mut(LSTM_UNITS))
+        {
+            add_assign(gates, &self.bias);
+            for (u, s) in state.chunks_exact_mut(2).enumerate() {
+                let i = sigmoid(gates[u]);
+                let f = sigmoid(gates[LSTM_UNITS + u]);
+                let z = gates[2 * LSTM_UNITS + u].tanh();
+                let o = sigmoid(gates[3 * LSTM_UNITS + u]);
+
+                let c = f * s[1] + i * z;
+                let h = o * c.tanh();
+                s[0] = h;
+                s[1] = c;
+                out[u] = h;
+            }
+        }
+    }
+}
+
+// Runs the stacked LSTM layers, returning the last layer's hidden state for
+// every stream.
+fn run_lstm_stack(
+    layers: &mut [LstmLayer; LSTM_LAYERS],
+    inputs: &[f32],
+    states: &mut [f32],
+) -> Vec<f32> {
+    let batch = states.len() / DTLN_BLOCK_LEN;
+    let mut hidden_1 = vec![0f32; batch * LSTM_UNITS];
+    let mut hidden_2 = vec![0f32; batch * LSTM_UNITS];
+    layers[0].step(0, inputs, states, &mut hidden_1);
+    layers[1].step(1, &hidden_1, states, &mut hidden_2);
+    hidden_2
+}
+
+// Model 1: magnitude spectrum -> 2x LSTM -> dense -> sigmoid mask.
+struct MaskModel {
+    lstm: [LstmLayer; LSTM_LAYERS],
+    dense: Dense,
+}
+
+impl MaskModel {
+    fn new(model: &[u8]) -> Result<Self> {
+        let mut reader = TensorReader {
+            tensors: read_const_tensors(model)?.into_iter(),
+        };
+        Ok(MaskModel {
+            lstm: [
+                LstmLayer::read(&mut reader, DTLN_FFT_OUT_SIZE)?,
+                LstmLayer::read(&mut reader, LSTM_UNITS)?,
+            ],
+            dense: Dense::read(&mut reader, &[DTLN_FFT_OUT_SIZE, LSTM_UNITS])?,
+        })
+    }
+
+    fn run(&mut self, in_mag: &[f32], states: &mut [f32], out_mask: &mut [f32]) {
+        let hidden = run_lstm_stack(&mut self.lstm, in_mag, states);
+        self.dense.forward(&hidden, out_mask);
+        for m in out_mask.iter_mut() {
+            *m = sigmoid(*m);
+        }
+    }
+}
+
+// Model 2: time domain block -> 1D conv encoder -> instant layer norm ->
+// 2x LSTM -> dense -> sigmoid mask on the encoded features -> 1D conv decoder.
+struct BlockModel {
+    encoder: Dense,
+    norm_epsilon: f32,
+    norm_gamma: Vec<f32>,
+    norm_beta: Vec<f32>,
+    lstm: [LstmLayer; LSTM_LAYERS],
+    dense: Dense,
+    decoder: Dense,
+}
+
+impl BlockModel {
+    fn new(model: &[u8]) -> Result<Self> {
+        let mut reader = TensorReader {
+            tensors: read_const_tensors(model)?.into_iter(),
+        };
+        Ok(BlockModel {
+            encoder: Dense::read(&mut reader, &[ENCODER_SIZE, 1, 1, DTLN_BLOCK_LEN])?,
+            norm_epsilon: reader.next(&[])?[0],
+            norm_gamma: reader.next(&[ENCODER_SIZE])?,
+            norm_beta: reader.next(&[ENCODER_SIZE])?,
+            lstm: [
+                LstmLayer::read(&mut reader, ENCODER_SIZE)?,
+                LstmLayer::read(&mut reader, LSTM_UNITS)?,
+            ],
+            dense: Dense::read(&mut reader, &[ENCODER_SIZE, LSTM_UNITS])?,
+            decoder: Dense::read(&mut reader, &[DTLN_BLOCK_LEN, 1, 1, ENCODER_SIZE])?,
+        })
+    }
+
+    fn run(&mut self, block: &[f32], states: &mut [f32], out_block: &mut [f32]) {
+        let batch = states.len() / DTLN_BLOCK_LEN;
+        let mut encoded = vec![0f32; batch * ENCODER_SIZE];
+        self.encoder.forward(block, &mut encoded);
+
+        let mut normalized = vec![0f32; batch * ENCODER_SIZE];
+        for (encoded, normalized) in encoded
+            .chunks_exact(ENCODER_SIZE)
+            .zip(normalized.chunks_exact_mut(ENCODER_SIZE))
+        {
+            let mean = encoded.iter().sum::<f32>() / ENCODER_SIZE as f32;
+            let variance =
+                encoded.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / ENCODER_SIZE as f32;
+            let inv_std = 1.0 / (variance + self.norm_epsilon).sqrt();
+            for (i, n) in normalized.iter_mut().enumerate() {
+                *n = (encoded[i] - mean) * inv_std * self.norm_gamma[i] + self.norm_beta[i];
+            }
+        }
+
+        let hidden = run_lstm_stack(&mut self.lstm, &normalized, states);
+
+        let mut mask = vec![0f32; batch * ENCODER_SIZE];
+        self.dense.forward(&hidden, &mut mask);
+        for (e, m) in encoded.iter_mut().zip(mask) {
+            *e *= sigmoid(m);
+        }
+
+        self.decoder.forward(&encoded, out_block);
+    }
+}
+
+/**
+ * Both DTLN models evaluated natively. The state buffers use the same
+ * [layer][unit][h, c] layout as the TFLite models so they are interchangeable.
+ *
+ * Every method accepts one or more independent streams stacked back to back,
+ * the batch size is taken from the length of the state buffer.
+ */
+pub struct DtlnNativeModel {
+    stage_1: MaskModel,
+    stage_2: BlockModel,
+}
+
+impl DtlnNativeModel {
+    pub fn new(model1: &[u8], model2: &[u8]) -> Result<Self> {
+        Ok(DtlnNativeModel {
+            stage_1: MaskModel::new(model1)?,
+            stage_2: BlockModel::new(model2)?,
+        })
+    }
+
+    /**
+     * Compute the suppression mask for a magnitude spectrum.
+     * @param in_mag DTLN_FFT_OUT_SIZE magnitudes per stream.
+     * @param states DTLN_BLOCK_LEN model 1 states per stream, updated in place.
+     * @param out_mask DTLN_FFT_OUT_SIZE mask values in [0, 1] per stream.
+     */
+    pub fn run_stage_1(&mut self, in_mag: &[f32], states: &mut [f32], out_mask: &mut [f32]) {
+        self.stage_1.run(in_mag, states, out_mask);
+    }
+
+    /**
+     * Refine a masked time domain block.
+     * @param block DTLN_BLOCK_LEN samples per stream.
+     * @param states DTLN_BLOCK_LEN model 2 states per stream, updated in place.
+     * @param out_block DTLN_BLOCK_LEN samples to overlap-add per stream.
+     */
+    pub fn run_stage_2(&mut self, block: &[f32], states: &mut [f32], out_block: &mut [f32]) {
+        self.stage_2.run(block, states, out_block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_engine::DtlnEngine;
+    use crate::dtln_utilities::read_wav_to_pcm32;
+
+    #[test]
+    fn test_native_matches_tflite() -> Result<()> {
+        let mut samples = vec![];
+        read_wav_to_pcm32("clips/restaurant_noisy.wav", &mut samples)?;
+        samples.truncate(16000 * 3);
+
+        let mut tflite_engine = DtlnEngine::new().unwrap();
+        let mut native_engine = DtlnEngine::new_native().unwrap();
+
+        let mut tflite_out = vec![0.0; samples.len()];
+        let mut native_out = vec![0.0; samples.len()];
+        tflite_engine.denoise(&samples, &mut tflite_out);
+        native_engine.denoise(&samples, &mut native_out);
+
+        let max_diff = tflite_out
+            .iter()
+            .zip(&native_out)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        // Shown with --nocapture, to track how close the backends are.
+        println!("native/tflite max difference: {}", max_diff);
+        assert!(max_diff < 1e-3, "max difference {}", max_diff);
+        Ok(())
+    }
+}
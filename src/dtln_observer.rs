@@ -0,0 +1,163 @@
+// Per-block inspection of the DTLN model, for visualizing what it does.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::constants::*;
+
+/**
+ * Spectra of one DTLN_BLOCK_SHIFT block, each DTLN_FFT_OUT_SIZE bins long.
+ */
+pub struct BlockSpectra<'a> {
+    // Magnitude spectrum of the input block.
+    pub in_mag: &'a [f32],
+    // Model 1 mask as applied, after the minimum gain floor.
+    pub out_mask: &'a [f32],
+    // Magnitude removed by the mask, in_mag * (1 - out_mask).
+    pub noise_estimate: &'a [f32],
+}
+
+/**
+ * Receives the spectra of every block processed by a DtlnEngine. Called on
+ * the audio thread, so implementations should return quickly.
+ */
+pub trait BlockObserver: Send {
+    fn on_block(&mut self, spectra: &BlockSpectra);
+}
+
+// Spectra kept per block: magnitude, mask and noise estimate.
+const RECORDED_SPECTRA: usize = 3;
+
+/**
+ * Observer which records every block, and writes them out as NPY or CSV for
+ * spectrogram rendering. Clones share the same recording, so one clone can be
+ * handed to the engine while another is kept to write the results.
+ */
+#[derive(Clone, Default)]
+pub struct SpectraRecorder {
+    // RECORDED_SPECTRA * DTLN_FFT_OUT_SIZE values per block.
+    frames: Arc<Mutex<Vec<f32>>>,
+}
+
+impl SpectraRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.frames.lock().unwrap().len() / (RECORDED_SPECTRA * DTLN_FFT_OUT_SIZE)
+    }
+
+    /**
+     * Write the recording, choosing the format from the file extension.
+     * @param path A .npy or .csv file.
+     */
+    pub fn write(&self, path: &str) -> Result<()> {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("npy") => self.write_npy(path),
+            Some("csv") => self.write_csv(path),
+            _ => Err(anyhow!("Unsupported spectra dump format: {}", path)),
+        }
+    }
+
+    /**
+     * Write a float32 array of shape (blocks, 3, DTLN_FFT_OUT_SIZE), where the
+     * second axis is magnitude, mask and noise estimate.
+     */
+    pub fn write_npy(&self, path: &str) -> Result<()> {
+        let frames = self.frames.lock().unwrap();
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let mut header = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+            frames.len() / (RECORDED_SPECTRA * DTLN_FFT_OUT_SIZE),
+            RECORDED_SPECTRA,
+            DTLN_FFT_OUT_SIZE
+        );
+        // Magic, version and header length take 10 bytes, and the header must
+        // end with a newline such that the data starts 64 byte aligned.
+        let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+        header.push_str(&" ".repeat(padding));
+        header.push('\n');
+
+        writer.write_all(b"\x93NUMPY\x01\x00")?;
+        writer.write_u16::<LittleEndian>(header.len() as u16)?;
+        writer.write_all(header.as_bytes())?;
+        for value in frames.iter() {
+            writer.write_f32::<LittleEndian>(*value)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /**
+     * Write one row per block and bin: block,bin,magnitude,mask,noise.
+     */
+    pub fn write_csv(&self, path: &str) -> Result<()> {
+        let frames = self.frames.lock().unwrap();
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "block,bin,magnitude,mask,noise")?;
+        for (block, frame) in frames
+            .chunks_exact(RECORDED_SPECTRA * DTLN_FFT_OUT_SIZE)
+            .enumerate()
+        {
+            let (in_mag, rest) = frame.split_at(DTLN_FFT_OUT_SIZE);
+            let (out_mask, noise_estimate) = rest.split_at(DTLN_FFT_OUT_SIZE);
+            for bin in 0..DTLN_FFT_OUT_SIZE {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    block, bin, in_mag[bin], out_mask[bin], noise_estimate[bin]
+                )?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl BlockObserver for SpectraRecorder {
+    fn on_block(&mut self, spectra: &BlockSpectra) {
+        let mut frames = self.frames.lock().unwrap();
+        frames.extend_from_slice(spectra.in_mag);
+        frames.extend_from_slice(spectra.out_mask);
+        frames.extend_from_slice(spectra.noise_estimate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_engine::DtlnEngine;
+
+    #[test]
+    fn test_recorder_receives_every_block() {
+        let samples = (0..4096)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+
+        let recorder = SpectraRecorder::new();
+        let mut engine = DtlnEngine::new_native().unwrap();
+        engine.set_observer(Some(Box::new(recorder.clone())));
+        let mut out = vec![0.0; samples.len()];
+        engine.denoise(&samples, &mut out);
+
+        assert_eq!(recorder.block_count(), samples.len() / DTLN_BLOCK_SHIFT);
+
+        let frames = recorder.frames.lock().unwrap();
+        let frame = &frames[..RECORDED_SPECTRA * DTLN_FFT_OUT_SIZE];
+        for bin in 0..DTLN_FFT_OUT_SIZE {
+            let in_mag = frame[bin];
+            let out_mask = frame[DTLN_FFT_OUT_SIZE + bin];
+            let noise_estimate = frame[2 * DTLN_FFT_OUT_SIZE + bin];
+            assert!((0.0..=1.0).contains(&out_mask));
+            assert!((noise_estimate - in_mag * (1.0 - out_mask)).abs() < 1e-6);
+        }
+    }
+}
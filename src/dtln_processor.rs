@@ -1,13 +1,24 @@
 // High level wrapper around DTLN that provides a simple interface.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 use neon::prelude::*;
 use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::Receiver;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::audio_pipeline::FrameAdapter;
 use crate::dtln_engine::{dtln_create, dtln_denoise, DtlnEngine};
+use crate::dtln_vad::VadResult;
+
+// DTLN sample rate.
+const SAMPLE_RATE: u32 = 16000;
+
+// Denoised samples, the voice activity detected while producing them and the
+// time it took.
+type ProcessorOutput = std::result::Result<(Vec<f32>, VadResult, Duration), String>;
 
 // The main interface trait that all processors must implement.
 pub trait DtlnProcessEngine<T> {
@@ -22,12 +33,16 @@ pub trait DtlnProcessEngine<T> {
 pub struct DtlnDeferredProcessor {
     pub engine: Arc<Mutex<DtlnEngine>>,
     sender_to_processor: Mutex<mpsc::Sender<Vec<f32>>>,
-    receiver_from_processor: Mutex<mpsc::Receiver<std::result::Result<Vec<f32>, String>>>,
+    receiver_from_processor: Mutex<mpsc::Receiver<ProcessorOutput>>,
     processing: Arc<AtomicBool>,
     first_sample: AtomicBool,
     processor_handle: Option<thread::JoinHandle<()>>,
+    last_vad: VadResult,
+    // Frames sent to the processor thread whose output was not received yet.
+    in_flight: usize,
 }
 
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 impl Finalize for DtlnDeferredProcessor {
     fn finalize<'a, C: neon::prelude::Context<'a>>(self, _: &mut C) {
         drop(self);
@@ -37,24 +52,39 @@ impl Finalize for DtlnDeferredProcessor {
 pub struct DenoiseResult {
     pub samples: Vec<f32>,
     pub processor_starved: bool,
+    pub vad: VadResult,
+    // Time spent denoising the returned samples.
+    pub processing_time: Duration,
 }
 
 pub struct DtlnImmediateProcessor {
-    pub engine: DtlnEngine,
+    pub engine: Arc<Mutex<DtlnEngine>>,
+}
+
+impl DtlnImmediateProcessor {
+    /**
+     * Create a processor around an existing engine.
+     */
+    pub fn with_engine(engine: Arc<Mutex<DtlnEngine>>) -> Self {
+        DtlnImmediateProcessor { engine }
+    }
 }
 
 impl DtlnProcessEngine<DtlnImmediateProcessor> for DtlnImmediateProcessor {
     fn new() -> Result<DtlnImmediateProcessor> {
-        Ok(DtlnImmediateProcessor {
-            engine: dtln_create().context("Unable to create engine.")?,
-        })
+        let engine = dtln_create().context("Unable to create engine.")?;
+        Ok(Self::with_engine(Arc::new(Mutex::new(engine))))
     }
     fn denoise(&mut self, input: &[f32]) -> Result<DenoiseResult> {
         let mut output = vec![0.0; input.len()];
-        dtln_denoise(&mut self.engine, input, &mut output)?;
+        let mut engine = self.engine.lock().unwrap();
+        let start = Instant::now();
+        dtln_denoise(&mut engine, input, &mut output)?;
         Ok(DenoiseResult {
             samples: output,
             processor_starved: false,
+            vad: engine.vad_result(),
+            processing_time: start.elapsed(),
         })
     }
 
@@ -64,6 +94,13 @@ impl DtlnProcessEngine<DtlnImmediateProcessor> for DtlnImmediateProcessor {
 }
 
 impl DtlnDeferredProcessor {
+    /**
+     * Voice activity of the samples returned by the most recent denoise() call.
+     */
+    pub fn last_vad(&self) -> VadResult {
+        self.last_vad
+    }
+
     /** If we don't already have a sample ready, and this is the first call, just return a silent
      * buffer. If we can process the input signal in real time, this means the next frame will
      * contain the denoised signal from the previous sample frame. And the next call will contain
@@ -83,11 +120,16 @@ impl DtlnDeferredProcessor {
             .lock()
             .unwrap()
             .recv_timeout(std::time::Duration::from_millis(max_sample_retrieval_ms));
+        if response.is_ok() {
+            self.in_flight -= 1;
+        }
         let result = match response {
             Ok(processor_result) => match processor_result {
-                Ok(samples) => DenoiseResult {
+                Ok((samples, vad, processing_time)) => DenoiseResult {
                     samples,
                     processor_starved: false,
+                    vad,
+                    processing_time,
                 },
                 Err(error) => {
                     // We can't process samples at all, it produced an error result.
@@ -99,6 +141,8 @@ impl DtlnDeferredProcessor {
                     // Could be the first sample, or the processor is starved.
                     samples: vec![0.0; samples_len],
                     processor_starved: !self.first_sample.load(std::sync::atomic::Ordering::SeqCst),
+                    vad: VadResult::default(),
+                    processing_time: Duration::ZERO,
                 }
             } // Silence
         };
@@ -110,13 +154,29 @@ impl DtlnDeferredProcessor {
 
         result
     }
-}
 
-impl DtlnProcessEngine<DtlnDeferredProcessor> for DtlnDeferredProcessor {
-    fn new() -> Result<DtlnDeferredProcessor> {
-        let engine = Arc::new(Mutex::new(
-            dtln_create().context("Unable to create engine")?,
-        ));
+    /**
+     * Wait for the frames still being processed and discard their output,
+     * then reset the engine. The next denoise() call returns silence again.
+     */
+    pub fn reset(&mut self) {
+        let receiver = self.receiver_from_processor.lock().unwrap();
+        while self.in_flight > 0 && receiver.recv().is_ok() {
+            self.in_flight -= 1;
+        }
+        drop(receiver);
+        self.in_flight = 0;
+
+        self.engine.lock().unwrap().reset();
+        self.first_sample
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.last_vad = VadResult::default();
+    }
+
+    /**
+     * Create a processor whose thread denoises with an existing engine.
+     */
+    pub fn with_engine(engine: Arc<Mutex<DtlnEngine>>) -> DtlnDeferredProcessor {
         let processing = Arc::new(AtomicBool::new(true));
         let processing_clone = processing.clone();
 
@@ -138,10 +198,14 @@ impl DtlnProcessEngine<DtlnDeferredProcessor> for DtlnDeferredProcessor {
                     Ok(samples) => {
                         let mut out_samples = vec![0.0; samples.len()];
                         let mut engine = engine_clone.lock().unwrap();
+                        let start = Instant::now();
                         let result = dtln_denoise(&mut engine, &samples, &mut out_samples);
                         match result {
                             Ok(_) => {
-                                sender_from_processor.send(Ok(out_samples)).unwrap();
+                                let vad = engine.vad_result();
+                                sender_from_processor
+                                    .send(Ok((out_samples, vad, start.elapsed())))
+                                    .unwrap();
                             }
                             Err(dtln_error) => {
                                 sender_from_processor
@@ -158,23 +222,38 @@ impl DtlnProcessEngine<DtlnDeferredProcessor> for DtlnDeferredProcessor {
             }
         });
 
-        Ok(DtlnDeferredProcessor {
+        DtlnDeferredProcessor {
             engine,
             sender_to_processor: Mutex::new(sender_to_processor),
             receiver_from_processor: Mutex::new(receiver_from_processor),
             processing,
             first_sample: AtomicBool::new(true),
             processor_handle: Some(processor_handle),
-        })
+            last_vad: VadResult::default(),
+            in_flight: 0,
+        }
+    }
+}
+
+impl DtlnProcessEngine<DtlnDeferredProcessor> for DtlnDeferredProcessor {
+    fn new() -> Result<DtlnDeferredProcessor> {
+        let engine = Arc::new(Mutex::new(
+            dtln_create().context("Unable to create engine")?,
+        ));
+        Ok(Self::with_engine(engine))
     }
 
     /**
      * Stops the processor thread. This will leave DtlnProcessor in a
      * state where it will always produce a poisoned result.
      *
-     * This should be called when we are done with the processor.
+     * This should be called when we are done with the processor. Calling it
+     * again has no effect.
      */
     fn stop(&mut self) {
+        if self.processor_handle.is_none() {
+            return;
+        }
         self.processing
             .store(false, std::sync::atomic::Ordering::SeqCst);
 
@@ -218,20 +297,252 @@ impl DtlnProcessEngine<DtlnDeferredProcessor> for DtlnDeferredProcessor {
             .unwrap()
             .send(samples.to_vec())
         {
-            Ok(_) => {}
+            Ok(_) => self.in_flight += 1,
             Err(e) => {
                 // We can't process samples at all, it produced an error result.
                 panic!("Error sending to processor: {}", e);
             }
         }
 
+        self.last_vad = processor_result.vad;
         Ok(processor_result)
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessorMode {
+    // Denoise on the calling thread.
+    Immediate,
+    // Denoise on a background thread, see DtlnDeferredProcessor.
+    Deferred,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessorOptions {
+    pub mode: ProcessorMode,
+    // Rate of the samples passed to denoise(). Other rates than 16khz are
+    // resampled, and then take input of any length.
+    pub sample_rate: u32,
+    // Threads of each TFLite interpreter.
+    pub threads: usize,
+    // Use the native model implementation instead of TFLite.
+    pub native: bool,
+    // Suppression level, see DtlnEngine::set_min_gain_db().
+    pub min_gain_db: Option<f32>,
+    // Buffer partial blocks so 16khz input of any length is accepted too, at
+    // the cost of DTLN_BLOCK_SHIFT - 1 samples of latency.
+    pub buffered: bool,
+}
+
+impl Default for ProcessorOptions {
+    fn default() -> Self {
+        ProcessorOptions {
+            mode: ProcessorMode::Deferred,
+            sample_rate: SAMPLE_RATE,
+            threads: 1,
+            native: false,
+            min_gain_db: None,
+            buffered: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessorStats {
+    // denoise() calls, and the samples passed to them.
+    pub calls: u64,
+    pub samples: u64,
+    // Calls which returned silence because the processor thread fell behind.
+    pub starved_calls: u64,
+    // Time spent denoising, in total and for the slowest call.
+    pub processing_time: Duration,
+    pub max_processing_time: Duration,
+}
+
+enum ProcessorKind {
+    Immediate(DtlnImmediateProcessor),
+    Deferred(DtlnDeferredProcessor),
+}
+
+impl ProcessorKind {
+    fn denoise(&mut self, samples: &[f32]) -> Result<DenoiseResult> {
+        match self {
+            ProcessorKind::Immediate(processor) => processor.denoise(samples),
+            ProcessorKind::Deferred(processor) => processor.denoise(samples),
+        }
+    }
+}
+
+/**
+ * An immediate or deferred processor chosen at runtime, with the options and
+ * statistics exposed by the Node module.
+ */
+pub struct DtlnProcessor {
+    options: ProcessorOptions,
+    pub engine: Arc<Mutex<DtlnEngine>>,
+    kind: ProcessorKind,
+    // Resampling to 16khz and block buffering, None when running unbuffered
+    // at 16khz.
+    adapter: Option<FrameAdapter>,
+    stats: ProcessorStats,
+    last_vad: VadResult,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Finalize for DtlnProcessor {}
+
+impl DtlnProcessor {
+    pub fn new(options: ProcessorOptions) -> Result<Self> {
+        let engine = if options.native {
+            DtlnEngine::new_native()
+        } else {
+            DtlnEngine::with_threads(options.threads)
+        };
+        let mut engine = engine.ok_or_else(|| anyhow!("Unable to create engine"))?;
+        if let Some(min_gain_db) = options.min_gain_db {
+            engine.set_min_gain_db(min_gain_db);
+            // Start at the configured level instead of ramping to it.
+            engine.reset();
+        }
+        let engine = Arc::new(Mutex::new(engine));
+
+        let adapter = match options.sample_rate {
+            SAMPLE_RATE if !options.buffered => None,
+            sample_rate => Some(FrameAdapter::new(sample_rate)?),
+        };
+        let kind = match options.mode {
+            ProcessorMode::Immediate => {
+                ProcessorKind::Immediate(DtlnImmediateProcessor::with_engine(engine.clone()))
+            }
+            ProcessorMode::Deferred => {
+                ProcessorKind::Deferred(DtlnDeferredProcessor::with_engine(engine.clone()))
+            }
+        };
+
+        Ok(DtlnProcessor {
+            options,
+            engine,
+            kind,
+            adapter,
+            stats: ProcessorStats::default(),
+            last_vad: VadResult::default(),
+        })
+    }
+
+    pub fn options(&self) -> ProcessorOptions {
+        self.options
+    }
+
+    /**
+     * Delay of the output in samples at the processor sample rate. This does
+     * not include deferred processing, whose output is also one denoise()
+     * call behind, since the length of a call is up to the caller.
+     */
+    pub fn latency(&self) -> usize {
+        let internal = self.engine.lock().unwrap().latency();
+        match self.adapter.as_ref() {
+            Some(adapter) => adapter.latency(internal),
+            None => internal,
+        }
+    }
+
+    /**
+     * Denoise samples into output, which must be at least as long. Unbuffered
+     * at 16khz, only whole DTLN_BLOCK_SHIFT blocks are denoised.
+     *
+     * @returns True if the processor thread is backed up.
+     */
+    pub fn denoise(&mut self, samples: &[f32], output: &mut [f32]) -> Result<bool> {
+        if output.len() < samples.len() {
+            return Err(anyhow!(
+                "Output buffer too small, {} vs {}",
+                output.len(),
+                samples.len()
+            ));
+        }
+
+        let mut results = Vec::new();
+        match self.adapter.as_mut() {
+            Some(adapter) => {
+                let kind = &mut self.kind;
+                let mut error = None;
+                adapter.process(samples, output, |frames| match kind.denoise(frames) {
+                    Ok(result) => {
+                        frames.clone_from(&result.samples);
+                        results.push(result);
+                    }
+                    Err(e) => error = Some(e),
+                });
+                if let Some(e) = error {
+                    return Err(e);
+                }
+            }
+            None => {
+                let result = self.kind.denoise(samples)?;
+                let len = result.samples.len().min(output.len());
+                output[..len].copy_from_slice(&result.samples[..len]);
+                results.push(result);
+            }
+        }
+
+        let processing_time = results.iter().map(|r| r.processing_time).sum::<Duration>();
+        let starved = results.iter().any(|r| r.processor_starved);
+        if let Some(result) = results.last() {
+            self.last_vad = result.vad;
+        }
+
+        self.stats.calls += 1;
+        self.stats.samples += samples.len() as u64;
+        self.stats.starved_calls += starved as u64;
+        self.stats.processing_time += processing_time;
+        self.stats.max_processing_time = self.stats.max_processing_time.max(processing_time);
+        Ok(starved)
+    }
+
+    /**
+     * Voice activity of the samples returned by the most recent denoise() call.
+     */
+    pub fn last_vad(&self) -> VadResult {
+        self.last_vad
+    }
+
+    pub fn stats(&self) -> ProcessorStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = ProcessorStats::default();
+    }
+
+    /**
+     * Discard all buffered audio and return the engine to its initial state,
+     * keeping its settings. Statistics are kept, see reset_stats().
+     */
+    pub fn reset(&mut self) {
+        match &mut self.kind {
+            ProcessorKind::Immediate(processor) => processor.engine.lock().unwrap().reset(),
+            ProcessorKind::Deferred(processor) => processor.reset(),
+        }
+        if let Some(adapter) = self.adapter.as_mut() {
+            adapter.reset();
+        }
+        self.last_vad = VadResult::default();
+    }
+
+    /**
+     * Stops a deferred processor's thread, see DtlnDeferredProcessor::stop().
+     */
+    pub fn stop(&mut self) {
+        if let ProcessorKind::Deferred(processor) = &mut self.kind {
+            processor.stop();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::DTLN_BLOCK_SHIFT;
 
     #[test]
     fn test_deferred_denoise() -> Result<()> {
@@ -293,4 +604,75 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_processor_options_and_reset() -> Result<()> {
+        let mut processor = DtlnProcessor::new(ProcessorOptions {
+            mode: ProcessorMode::Immediate,
+            sample_rate: 48000,
+            native: true,
+            min_gain_db: Some(-12.0),
+            ..ProcessorOptions::default()
+        })?;
+        let samples = (0..48000)
+            .map(|i| (i as f32 * 0.02).sin() * 0.3 + rand::random::<f32>() * 0.05)
+            .collect::<Vec<f32>>();
+
+        let run = |processor: &mut DtlnProcessor| -> Result<Vec<f32>> {
+            let mut output = vec![0.0; samples.len()];
+            // Not a multiple of DTLN_BLOCK_SHIFT at either rate.
+            for (input, output) in samples.chunks(1000).zip(output.chunks_mut(1000)) {
+                assert!(!processor.denoise(input, output)?);
+            }
+            Ok(output)
+        };
+
+        let first = run(&mut processor)?;
+        assert!(first.iter().any(|s| *s != 0.0));
+        processor.reset();
+        assert_eq!(run(&mut processor)?, first);
+
+        let stats = processor.stats();
+        assert_eq!(stats.calls, 96);
+        assert_eq!(stats.samples, 96000);
+        assert_eq!(stats.starved_calls, 0);
+        assert!(stats.max_processing_time <= stats.processing_time);
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffered_frame_sizes() -> Result<()> {
+        let options = ProcessorOptions {
+            mode: ProcessorMode::Immediate,
+            native: true,
+            ..ProcessorOptions::default()
+        };
+        let mut blocks = DtlnProcessor::new(options)?;
+        let mut buffered = DtlnProcessor::new(ProcessorOptions {
+            buffered: true,
+            ..options
+        })?;
+        let samples = (0..16000)
+            .map(|i| (i as f32 * 0.02).sin() * 0.3 + rand::random::<f32>() * 0.05)
+            .collect::<Vec<f32>>();
+
+        let mut expected = vec![0.0; samples.len()];
+        blocks.denoise(&samples, &mut expected)?;
+        let mut output = vec![0.0; samples.len()];
+        // Render quanta, and lengths unrelated to DTLN_BLOCK_SHIFT.
+        let mut offset = 0;
+        for len in [128, 100, 1, 333].iter().cycle() {
+            let end = (offset + len).min(samples.len());
+            buffered.denoise(&samples[offset..end], &mut output[offset..end])?;
+            offset = end;
+            if offset == samples.len() {
+                break;
+            }
+        }
+
+        let delay = buffered.latency() - blocks.latency();
+        assert_eq!(delay, DTLN_BLOCK_SHIFT - 1);
+        assert_eq!(output[delay..], expected[..samples.len() - delay]);
+        Ok(())
+    }
 }
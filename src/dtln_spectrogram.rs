@@ -0,0 +1,151 @@
+// Spectrogram rendering, for comparing signals before and after denoising.
+
+use std::fs::File;
+use std::io::BufWriter;
+
+use anyhow::{anyhow, Result};
+use realfft::RealFftPlanner;
+
+use crate::constants::*;
+
+// Range of the color scale, below the loudest bin of all panels.
+const DYNAMIC_RANGE_DB: f32 = 80.0;
+
+// Width in pixels of the gap between panels.
+const PANEL_GAP: usize = 4;
+
+// Color map stops from quiet to loud, similar to matplotlib's magma.
+const COLOR_STOPS: [[f32; 3]; 5] = [
+    [0.0, 0.0, 4.0],
+    [81.0, 18.0, 124.0],
+    [183.0, 55.0, 121.0],
+    [252.0, 137.0, 97.0],
+    [252.0, 253.0, 191.0],
+];
+
+/**
+ * Magnitude spectra of a signal, framed like the engine: DTLN_BLOCK_LEN
+ * samples per frame, advanced by DTLN_BLOCK_SHIFT. Frames are Hann windowed to
+ * reduce leakage in the rendered image.
+ *
+ * @returns DTLN_FFT_OUT_SIZE magnitudes per frame, frame after frame.
+ */
+pub fn stft_magnitudes(samples: &[f32]) -> Vec<f32> {
+    let mut planner = RealFftPlanner::<f32>::new();
+    let r2c = planner.plan_fft_forward(DTLN_BLOCK_LEN);
+    let window = (0..DTLN_BLOCK_LEN)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / DTLN_BLOCK_LEN as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect::<Vec<f32>>();
+
+    let mut fft_in = r2c.make_input_vec();
+    let mut fft_spectrum = r2c.make_output_vec();
+    let mut magnitudes = vec![];
+    for start in (0..samples.len().saturating_sub(DTLN_BLOCK_LEN - 1)).step_by(DTLN_BLOCK_SHIFT) {
+        let frame = &samples[start..start + DTLN_BLOCK_LEN];
+        for i in 0..DTLN_BLOCK_LEN {
+            fft_in[i] = frame[i] * window[i];
+        }
+        r2c.process(&mut fft_in, &mut fft_spectrum).unwrap();
+        magnitudes.extend(fft_spectrum.iter().map(|bin| bin.norm()));
+    }
+    magnitudes
+}
+
+/**
+ * Render the spectrograms of several equally long signals side by side into
+ * a PNG, sharing one decibel scale. Time runs left to right, and frequency
+ * bottom to top.
+ *
+ * @param signals The signals, in left to right panel order.
+ * @param path Path of the PNG file to write.
+ */
+pub fn write_spectrogram_png(signals: &[&[f32]], path: &str) -> Result<()> {
+    let spectra = signals
+        .iter()
+        .map(|signal| stft_magnitudes(signal))
+        .collect::<Vec<_>>();
+    let frame_count = spectra.first().map_or(0, |s| s.len() / DTLN_FFT_OUT_SIZE);
+    if frame_count == 0 {
+        return Err(anyhow!(
+            "Signals must be at least {} samples long",
+            DTLN_BLOCK_LEN
+        ));
+    }
+    if spectra
+        .iter()
+        .any(|s| s.len() != frame_count * DTLN_FFT_OUT_SIZE)
+    {
+        return Err(anyhow!("All signals must have the same length"));
+    }
+
+    let peak = spectra
+        .iter()
+        .flatten()
+        .fold(f32::MIN_POSITIVE, |peak, magnitude| peak.max(*magnitude));
+    let peak_db = 20.0 * peak.log10();
+
+    let width = spectra.len() * (frame_count + PANEL_GAP) - PANEL_GAP;
+    let height = DTLN_FFT_OUT_SIZE;
+    let mut pixels = vec![255u8; width * height * 3];
+    for (panel, spectrum) in spectra.iter().enumerate() {
+        let x_offset = panel * (frame_count + PANEL_GAP);
+        for (frame, magnitudes) in spectrum.chunks_exact(DTLN_FFT_OUT_SIZE).enumerate() {
+            for (bin, magnitude) in magnitudes.iter().enumerate() {
+                let db = 20.0 * magnitude.max(f32::MIN_POSITIVE).log10();
+                let level = ((db - peak_db) / DYNAMIC_RANGE_DB + 1.0).clamp(0.0, 1.0);
+                let y = height - 1 - bin;
+                let offset = (y * width + x_offset + frame) * 3;
+                pixels[offset..offset + 3].copy_from_slice(&color(level));
+            }
+        }
+    }
+
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    Ok(())
+}
+
+// Map a level in [0, 1] onto COLOR_STOPS.
+fn color(level: f32) -> [u8; 3] {
+    let position = level * (COLOR_STOPS.len() - 1) as f32;
+    let index = (position as usize).min(COLOR_STOPS.len() - 2);
+    let fraction = position - index as f32;
+    let (low, high) = (COLOR_STOPS[index], COLOR_STOPS[index + 1]);
+    [0, 1, 2].map(|c| (low[c] + (high[c] - low[c]) * fraction).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spectrogram_dimensions() -> Result<()> {
+        let samples = (0..16000)
+            .map(|i| (i as f32 * 0.05).sin() * 0.2)
+            .collect::<Vec<f32>>();
+        let path =
+            std::env::temp_dir().join(format!("dtln_spectrogram_{}.png", std::process::id()));
+        let path = path.to_string_lossy();
+        write_spectrogram_png(&[&samples, &samples, &samples], &path)?;
+
+        let decoder = png::Decoder::new(File::open(&*path)?);
+        let reader = decoder.read_info()?;
+        let info = reader.info();
+        // (16000 - DTLN_BLOCK_LEN) / DTLN_BLOCK_SHIFT + 1 frames per panel.
+        let frame_count = 122;
+        assert_eq!(info.width as usize, 3 * frame_count + 2 * PANEL_GAP);
+        assert_eq!(info.height as usize, DTLN_FFT_OUT_SIZE);
+        std::fs::remove_file(&*path)?;
+
+        assert!(write_spectrogram_png(&[&samples[..DTLN_BLOCK_LEN - 1]], &path).is_err());
+        assert!(write_spectrogram_png(&[&samples, &samples[..8000]], &path).is_err());
+        Ok(())
+    }
+}
@@ -7,7 +7,13 @@ use std::path::Path;
 use std::sync::Mutex;
 use wav::Header;
 
-use crate::dtln_engine::{dtln_create, dtln_denoise, DtlnEngine};
+use crate::audio_pipeline::AudioPipeline;
+use crate::dtln_agc::AgcConfig;
+use crate::dtln_engine::DtlnEngine;
+use crate::dtln_filters::PreFilterConfig;
+use crate::dtln_limiter::{ClipStats, LimiterConfig};
+use crate::dtln_processor::{DtlnProcessor, ProcessorMode, ProcessorOptions, ProcessorStats};
+use crate::dtln_vad::VadResult;
 
 pub fn write_pcm32_to_wav(samples: Vec<f32>, filename: &str, audiorate: u32) -> Result<()> {
     // Convert to s16
@@ -17,18 +23,58 @@ pub fn write_pcm32_to_wav(samples: Vec<f32>, filename: &str, audiorate: u32) ->
     Ok(())
 }
 
+/**
+ * Write 16 bit mono samples, scaled such that read_wav_to_pcm32 reads back
+ * the same values to within one quantization step.
+ */
+pub fn write_pcm16_to_wav(samples: &[f32], filename: &str, audiorate: u32) -> Result<()> {
+    let mut data = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let value = (sample * u16::MAX as f32).round();
+        if value < i16::MIN as f32 || value > i16::MAX as f32 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Sample {} does not fit in 16 bits", sample),
+            ));
+        }
+        data.push(value as i16);
+    }
+
+    let header = Header::new(wav::WAV_FORMAT_PCM, 1, audiorate, 16);
+    let mut writer = File::create(Path::new(filename))?;
+    wav::write(header, &wav::BitDepth::Sixteen(data), &mut writer)?;
+    Ok(())
+}
+
+/**
+ * Read a mono wav file, returning its sample rate. 16 bit samples are scaled
+ * as in write_pcm16_to_wav, 32 bit float samples, as written by
+ * write_pcm32_to_wav, are read unchanged.
+ */
 pub fn read_wav_to_pcm32(input: &str, samples: &mut Vec<f32>) -> Result<u32> {
     samples.clear();
     let mut inp_file = File::open(Path::new(input))?;
 
     let (header, data) = wav::read(&mut inp_file)?;
-    let data = data.try_into_sixteen().unwrap();
 
-    samples.reserve(data.len());
+    let data = match data {
+        wav::BitDepth::Sixteen(data) if header.channel_count == 1 => data,
+        wav::BitDepth::ThirtyTwoFloat(data) if header.channel_count == 1 => {
+            samples.extend(data);
+            return Ok(header.sampling_rate);
+        }
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Expected 16 bit or 32 bit float mono, got {} bit with {} channels",
+                    header.bits_per_sample, header.channel_count
+                ),
+            ))
+        }
+    };
 
-    // The sample clips are only 16 bit mono.
-    assert_eq!(header.bits_per_sample, 16);
-    assert_eq!(header.channel_count, 1);
+    samples.reserve(data.len());
 
     // Convert 16 bit pcm samples in data to 32-bit float
     for sample in data.iter() {
@@ -47,84 +93,321 @@ pub fn read_wav_to_pcm32(input: &str, samples: &mut Vec<f32>) -> Result<u32> {
 
 const WASM_AUDIO_BLOCK_SIZE: usize = 512;
 
-#[allow(non_camel_case_types)]
-#[repr(C)]
-struct audio_buffer {
-    data: [f32; WASM_AUDIO_BLOCK_SIZE],
+struct EngineEntry {
+    processor: DtlnProcessor,
+    // Frames are written to input and denoised into output. Both hold
+    // frame_size samples, WASM_AUDIO_BLOCK_SIZE until resized.
+    input: Vec<f32>,
+    output: Vec<f32>,
 }
 
-static ENGINE_MAP: Lazy<Mutex<HashMap<u32, DtlnEngine>>> = Lazy::new(|| Mutex::new(HashMap::new()));
-static AUDIO_BUFFER_MAP: Lazy<Mutex<HashMap<u32, audio_buffer>>> =
+static ENGINE_MAP: Lazy<Mutex<HashMap<u32, EngineEntry>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 static CURRENT_MAP_ID: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
 
 /**
- * Create a new DtlnEngine and return a unique id for it.
+ * Allocate an id for a new engine or pipeline, under a single lock so
+ * concurrent creates never share one.
  */
-pub fn dtln_create_global() -> u32 {
-    let mut engine_map = ENGINE_MAP.lock().unwrap();
-    let mut memory_map = AUDIO_BUFFER_MAP.lock().unwrap();
+fn next_map_id() -> u32 {
+    let mut current_id = CURRENT_MAP_ID.lock().unwrap();
+    let id = *current_id;
+    *current_id += 1;
+    id
+}
 
-    let engine = dtln_create();
-    let id = *CURRENT_MAP_ID.lock().unwrap();
-    *CURRENT_MAP_ID.lock().unwrap() += 1;
+struct PipelineEntry {
+    pipeline: AudioPipeline,
+    // Frames are processed in place. Holds WASM_AUDIO_BLOCK_SIZE samples
+    // until a longer frame is requested.
+    buffer: Vec<f32>,
+    // Copy of the input, so the pipeline can write into buffer.
+    scratch: Vec<f32>,
+}
 
-    let Some(engine) = engine else {
-        panic!("Failed to create DtlnEngine");
-    };
+static PIPELINE_MAP: Lazy<Mutex<HashMap<u32, PipelineEntry>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
-    engine_map.insert(id, engine);
-    memory_map.insert(
+/**
+ * Create a new immediate DtlnProcessor and return a unique id for it. Partial
+ * blocks are buffered, so frames of any length can be denoised.
+ * @param options Processor options, the mode and buffering are ignored.
+ */
+pub fn dtln_create_global(options: ProcessorOptions) -> anyhow::Result<u32> {
+    let processor = DtlnProcessor::new(ProcessorOptions {
+        // There are no threads to defer processing to.
+        mode: ProcessorMode::Immediate,
+        buffered: true,
+        ..options
+    })?;
+
+    let mut engine_map = ENGINE_MAP.lock().unwrap();
+
+    let id = next_map_id();
+
+    engine_map.insert(
         id,
-        audio_buffer {
-            data: [0.0; WASM_AUDIO_BLOCK_SIZE],
+        EngineEntry {
+            processor,
+            input: vec![0.0; WASM_AUDIO_BLOCK_SIZE],
+            output: vec![0.0; WASM_AUDIO_BLOCK_SIZE],
         },
     );
-    id
+    Ok(id)
 }
 
-pub fn dtln_destroy_global(id: u32) {
+// Run f with the processor of an id.
+fn with_entry<T>(id: u32, f: impl FnOnce(&mut EngineEntry) -> T) -> T {
     let mut engine_map = ENGINE_MAP.lock().unwrap();
-    let mut memory_map = AUDIO_BUFFER_MAP.lock().unwrap();
+    let Some(entry) = engine_map.get_mut(&id) else {
+        panic!("Engine not found for {}", id);
+    };
+    f(entry)
+}
 
-    engine_map.remove(&id);
-    memory_map.remove(&id);
+// Run f with the engine of a processor.
+fn with_engine<T>(id: u32, f: impl FnOnce(&mut DtlnEngine) -> T) -> T {
+    with_entry(id, |entry| {
+        let mut engine = entry.processor.engine.lock().unwrap();
+        f(&mut engine)
+    })
 }
 
-pub fn dtln_get_audio_buffer_raw_ptr(id: u32) -> *const f32 {
-    let mut memory_map = AUDIO_BUFFER_MAP.lock().unwrap();
-    let buffer = memory_map.get_mut(&id);
-    if buffer.is_none() {
-        panic!("Audio buffer not found for {}", id);
-    }
-    let buffer = buffer.unwrap();
-    buffer.data.as_ptr()
+pub fn dtln_destroy_global(id: u32) {
+    ENGINE_MAP.lock().unwrap().remove(&id);
 }
 
 /**
- * Denoise a block of samples.
+ * Resize the input and output buffers to frame_size samples. Pointers from
+ * before the call are invalidated.
  * @param id The unique id of the engine to use.
  */
-pub fn dtln_denoise_global(id: u32) -> Result<()> {
-    let mut engine_map = ENGINE_MAP.lock().unwrap();
+pub fn dtln_set_frame_size_global(id: u32, frame_size: usize) {
+    with_entry(id, |entry| {
+        entry.input = vec![0.0; frame_size];
+        entry.output = vec![0.0; frame_size];
+    });
+}
 
-    let engine = engine_map.get_mut(&id);
-    if engine.is_none() {
-        panic!("Engine not found for {}", id);
+pub fn dtln_frame_size_global(id: u32) -> usize {
+    with_entry(id, |entry| entry.input.len())
+}
+
+pub fn dtln_get_input_buffer_raw_ptr(id: u32) -> *mut f32 {
+    with_entry(id, |entry| entry.input.as_mut_ptr())
+}
+
+pub fn dtln_get_output_buffer_raw_ptr(id: u32) -> *const f32 {
+    with_entry(id, |entry| entry.output.as_ptr())
+}
+
+/**
+ * Set the minimum gain of the suppression mask, in dB.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_set_min_gain_db_global(id: u32, min_gain_db: f32) {
+    with_engine(id, |engine| engine.set_min_gain_db(min_gain_db));
+}
+
+/**
+ * Set the dry/wet mix, 1.0 is fully denoised.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_set_mix_global(id: u32, wet: f32) {
+    with_engine(id, |engine| engine.set_mix(wet));
+}
+
+/**
+ * Enable automatic gain control, or disable it with None.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_set_agc_global(id: u32, config: Option<AgcConfig>) {
+    with_engine(id, |engine| engine.set_agc(config));
+}
+
+/**
+ * Enable the output peak limiter, or disable it with None.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_set_limiter_global(id: u32, config: Option<LimiterConfig>) {
+    with_engine(id, |engine| engine.set_limiter(config));
+}
+
+/**
+ * Clipping counters of the engine output, optionally resetting them.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_clip_stats_global(id: u32, reset: bool) -> ClipStats {
+    with_engine(id, |engine| {
+        let stats = engine.clip_stats();
+        if reset {
+            engine.reset_clip_stats();
+        }
+        stats
+    })
+}
+
+/**
+ * Configure the filters run on the input before the model.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_set_pre_filter_global(id: u32, config: PreFilterConfig) {
+    with_engine(id, |engine| engine.set_pre_filter(config));
+}
+
+/**
+ * Voice activity detected by the most recent dtln_denoise_global call.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_vad_global(id: u32) -> VadResult {
+    with_entry(id, |entry| entry.processor.last_vad())
+}
+
+/**
+ * Processing statistics, optionally resetting them.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_stats_global(id: u32, reset: bool) -> ProcessorStats {
+    with_entry(id, |entry| {
+        let stats = entry.processor.stats();
+        if reset {
+            entry.processor.reset_stats();
+        }
+        stats
+    })
+}
+
+/**
+ * Return the processor to its initial state, keeping its settings.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_reset_global(id: u32) {
+    with_entry(id, |entry| entry.processor.reset());
+}
+
+/**
+ * Delay of the output in samples, at the processor sample rate.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_latency_global(id: u32) -> usize {
+    with_entry(id, |entry| entry.processor.latency())
+}
+
+/**
+ * Denoise the first sample_count samples of the input buffer into the output
+ * buffer. Returns true if the processor is backed up.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_denoise_global(id: u32, sample_count: usize) -> Result<bool> {
+    with_entry(id, |entry| {
+        let sample_count = sample_count.min(entry.input.len());
+        entry
+            .processor
+            .denoise(&entry.input[..sample_count], &mut entry.output)
+            .map_err(|e| std::io::Error::other(e.to_string()))
+    })
+}
+
+/**
+ * Create an AudioPipeline from a JSON config and return a unique id for it.
+ */
+pub fn dtln_pipeline_create_global(config_json: &str) -> anyhow::Result<u32> {
+    let pipeline = AudioPipeline::from_json(config_json)?;
+
+    let id = next_map_id();
+
+    PIPELINE_MAP.lock().unwrap().insert(
+        id,
+        PipelineEntry {
+            pipeline,
+            buffer: vec![0.0; WASM_AUDIO_BLOCK_SIZE],
+            scratch: Vec::with_capacity(WASM_AUDIO_BLOCK_SIZE),
+        },
+    );
+    Ok(id)
+}
+
+pub fn dtln_pipeline_destroy_global(id: u32) {
+    PIPELINE_MAP.lock().unwrap().remove(&id);
+}
+
+/**
+ * Return the pipeline buffer, grown to hold at least frame_size samples.
+ * Growing invalidates pointers from before the call.
+ * @param id The unique id of the pipeline to use.
+ */
+pub fn dtln_pipeline_get_buffer_raw_ptr(id: u32, frame_size: usize) -> *mut f32 {
+    let mut pipeline_map = PIPELINE_MAP.lock().unwrap();
+    let Some(entry) = pipeline_map.get_mut(&id) else {
+        panic!("Pipeline not found for {}", id);
+    };
+    if entry.buffer.len() < frame_size {
+        entry.buffer.resize(frame_size, 0.0);
     }
-    let engine = engine.unwrap();
-
-    let mut memory_map = AUDIO_BUFFER_MAP.lock().unwrap();
-    let audio_buffer = memory_map.get_mut(&id).unwrap();
-
-    let mut out = [0.0; WASM_AUDIO_BLOCK_SIZE];
-    if dtln_denoise(engine, &audio_buffer.data, &mut out).is_ok() {
-        audio_buffer.data.copy_from_slice(&out);
-        Ok(())
-    } else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Failed to denoise",
-        ))
+    entry.buffer.as_mut_ptr()
+}
+
+/**
+ * Process the first sample_count samples of the pipeline buffer in place.
+ * Fails if the buffer is shorter, see dtln_pipeline_get_buffer_raw_ptr().
+ * @param id The unique id of the pipeline to use.
+ */
+pub fn dtln_pipeline_process_global(id: u32, sample_count: usize) -> Result<()> {
+    let mut pipeline_map = PIPELINE_MAP.lock().unwrap();
+    let Some(entry) = pipeline_map.get_mut(&id) else {
+        panic!("Pipeline not found for {}", id);
+    };
+
+    if sample_count > entry.buffer.len() {
+        return Err(std::io::Error::other(format!(
+            "{} samples exceed the pipeline buffer of {}",
+            sample_count,
+            entry.buffer.len()
+        )));
+    }
+    entry.scratch.clear();
+    entry
+        .scratch
+        .extend_from_slice(&entry.buffer[..sample_count]);
+    entry
+        .pipeline
+        .process(&entry.scratch, &mut entry.buffer[..sample_count]);
+    Ok(())
+}
+
+/**
+ * Delay of the pipeline output in samples, at the pipeline sample rate.
+ * @param id The unique id of the pipeline to use.
+ */
+pub fn dtln_pipeline_latency_global(id: u32) -> usize {
+    let pipeline_map = PIPELINE_MAP.lock().unwrap();
+    let Some(entry) = pipeline_map.get(&id) else {
+        panic!("Pipeline not found for {}", id);
+    };
+    entry.pipeline.latency()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_buffer_grows() {
+        let id = dtln_pipeline_create_global(r#"{"stages": [{"type": "gain", "db": 6}]}"#).unwrap();
+        let frame_size = WASM_AUDIO_BLOCK_SIZE * 3;
+
+        // Frames longer than the buffer are rejected, not cut short.
+        assert!(dtln_pipeline_process_global(id, frame_size).is_err());
+
+        let buffer = dtln_pipeline_get_buffer_raw_ptr(id, frame_size);
+        let frame = unsafe { std::slice::from_raw_parts_mut(buffer, frame_size) };
+        frame.fill(0.25);
+        dtln_pipeline_process_global(id, frame_size).unwrap();
+        // The whole frame comes back, past the latency of the 16khz framing.
+        let latency = dtln_pipeline_latency_global(id);
+        let frame = unsafe { std::slice::from_raw_parts(buffer, frame_size) };
+        assert!(frame[latency..].iter().all(|s| (s - 0.5).abs() < 0.01));
+
+        dtln_pipeline_destroy_global(id);
     }
 }
@@ -0,0 +1,169 @@
+// Voice activity detection derived from the model 1 suppression mask.
+//
+// DTLN keeps bins it considers speech and suppresses the rest, so the share of
+// the input energy surviving the mask is a good speech indicator. Per block,
+// the raw probability is the geometric mean of the mean mask value and the
+// output/input energy ratio over the speech band, which is then smoothed
+// (fast attack, slow release) and held for a hangover period.
+
+use crate::constants::*;
+
+// Speech band, roughly 125 Hz to 4 kHz at 31.25 Hz per bin.
+const SPEECH_BIN_START: usize = 4;
+const SPEECH_BIN_END: usize = 128;
+
+// Blocks quieter than this (mean bin power) are never speech.
+const SILENCE_POWER: f32 = 1e-7;
+
+const ATTACK: f32 = 0.5;
+const RELEASE: f32 = 0.9;
+
+const SPEECH_THRESHOLD: f32 = 0.5;
+
+// Blocks to keep reporting speech after the probability drops (~200 ms).
+const HANGOVER_BLOCKS: u32 = 25;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct VadResult {
+    // Smoothed speech probability in [0, 1].
+    pub speech_probability: f32,
+    // Thresholded probability, including hangover.
+    pub is_speech: bool,
+}
+
+impl VadResult {
+    /**
+     * Combine the results of consecutive blocks, keeping the strongest
+     * indication of speech.
+     */
+    pub fn merge(self, other: VadResult) -> VadResult {
+        VadResult {
+            speech_probability: self.speech_probability.max(other.speech_probability),
+            is_speech: self.is_speech || other.is_speech,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DtlnVad {
+    probability: f32,
+    hangover: u32,
+}
+
+impl DtlnVad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Update the detector with one block.
+     * @param in_mag DTLN_FFT_OUT_SIZE input magnitudes.
+     * @param out_mask DTLN_FFT_OUT_SIZE model 1 mask values.
+     */
+    pub fn process_block(&mut self, in_mag: &[f32], out_mask: &[f32]) -> VadResult {
+        debug_assert_eq!(in_mag.len(), DTLN_FFT_OUT_SIZE);
+
+        let mut in_power = 0.0;
+        let mut out_power = 0.0;
+        let mut mask_sum = 0.0;
+        for i in SPEECH_BIN_START..SPEECH_BIN_END {
+            let power = in_mag[i] * in_mag[i];
+            in_power += power;
+            out_power += power * out_mask[i] * out_mask[i];
+            mask_sum += out_mask[i];
+        }
+
+        let bins = (SPEECH_BIN_END - SPEECH_BIN_START) as f32;
+        let raw = if in_power / bins < SILENCE_POWER {
+            0.0
+        } else {
+            let energy_ratio = out_power / in_power;
+            let mean_mask = mask_sum / bins;
+            (energy_ratio * mean_mask).sqrt().clamp(0.0, 1.0)
+        };
+
+        let smoothing = if raw > self.probability {
+            ATTACK
+        } else {
+            RELEASE
+        };
+        self.probability = smoothing * self.probability + (1.0 - smoothing) * raw;
+
+        if self.probability >= SPEECH_THRESHOLD {
+            self.hangover = HANGOVER_BLOCKS;
+        } else {
+            self.hangover = self.hangover.saturating_sub(1);
+        }
+
+        VadResult {
+            speech_probability: self.probability,
+            is_speech: self.hangover > 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_engine::DtlnEngine;
+
+    #[test]
+    fn test_vad_noise_and_burst() {
+        // Blocks of silence, low noise, a harmonic burst and noise again.
+        let (noise_start, burst_start, burst_end, end) = (25, 125, 250, 375);
+        let mut seed = 1u32;
+        let samples = (0..end * DTLN_BLOCK_SHIFT)
+            .map(|i| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let block = i / DTLN_BLOCK_SHIFT;
+                if block < noise_start {
+                    return 0.0;
+                }
+                let noise = ((seed >> 8) as f32 / (1 << 24) as f32 - 0.5) * 0.02;
+                if !(burst_start..burst_end).contains(&block) {
+                    return noise;
+                }
+                let t = i as f32 / 16000.0;
+                let burst = (1..=20)
+                    .map(|h| {
+                        (2.0 * std::f32::consts::PI * 150.0 * h as f32 * t).sin()
+                            / (h as f32).sqrt()
+                    })
+                    .sum::<f32>();
+                noise + 0.05 * burst
+            })
+            .collect::<Vec<f32>>();
+
+        let mut engine = DtlnEngine::new_native().unwrap();
+        let mut out = vec![0.0; DTLN_BLOCK_SHIFT];
+        let results = samples
+            .chunks(DTLN_BLOCK_SHIFT)
+            .map(|block| {
+                engine.denoise(block, &mut out);
+                engine.vad_result()
+            })
+            .collect::<Vec<_>>();
+
+        assert!(results[..noise_start]
+            .iter()
+            .all(|r| r.speech_probability == 0.0 && !r.is_speech));
+        // The onset of the noise briefly reads as speech, until the model has
+        // adapted to it (~400 ms).
+        assert!(results[noise_start + 50..burst_start]
+            .iter()
+            .all(|r| r.speech_probability < SPEECH_THRESHOLD && !r.is_speech));
+        assert!(results[burst_start + 10..burst_end]
+            .iter()
+            .all(|r| r.is_speech));
+
+        // Speech is reported for HANGOVER_BLOCKS after the probability drops.
+        let last_above = results
+            .iter()
+            .rposition(|r| r.speech_probability >= SPEECH_THRESHOLD)
+            .unwrap();
+        assert!(last_above >= burst_end);
+        let hangover = last_above + HANGOVER_BLOCKS as usize;
+        assert!(results[last_above..hangover].iter().all(|r| r.is_speech));
+        assert!(results[hangover..].iter().all(|r| !r.is_speech));
+    }
+}
@@ -1,40 +1,313 @@
-#[cfg(target_os = "macos")]
+#[cfg(not(target_os = "emscripten"))]
 use anyhow::Result;
 
-#[cfg(target_os = "macos")]
-use dtln_rs::dtln_processor::{DtlnProcessEngine, DtlnDeferredProcessor};
+#[cfg(not(target_os = "emscripten"))]
+use anyhow::anyhow;
 
-#[cfg(target_os = "macos")]
+#[cfg(not(target_os = "emscripten"))]
+use dtln_rs::audio_pipeline::AudioPipeline;
+
+#[cfg(not(target_os = "emscripten"))]
+use dtln_rs::dtln_dataset::{
+    evaluate_dataset, evaluations_to_json, mean_metrics, synthesize_dataset, SynthesisOptions,
+};
+
+#[cfg(not(target_os = "emscripten"))]
+use dtln_rs::dtln_metrics::{evaluate, QualityMetrics};
+
+#[cfg(not(target_os = "emscripten"))]
+use dtln_rs::dtln_observer::{BlockObserver, SpectraRecorder};
+
+#[cfg(not(target_os = "emscripten"))]
+use dtln_rs::dtln_offline::{denoise_offline, OfflineOptions};
+
+#[cfg(not(target_os = "emscripten"))]
+use dtln_rs::dtln_processor::{DtlnDeferredProcessor, DtlnProcessEngine};
+
+#[cfg(not(target_os = "emscripten"))]
+use dtln_rs::dtln_spectrogram::write_spectrogram_png;
+
+#[cfg(not(target_os = "emscripten"))]
 use dtln_rs::dtln_utilities::{read_wav_to_pcm32, write_pcm32_to_wav};
 
-#[cfg(target_os = "macos")]
+#[cfg(not(target_os = "emscripten"))]
 const BLOCK_SIZE: usize = 1024;
 
-#[cfg(target_os = "macos")]
+#[cfg(not(target_os = "emscripten"))]
 const EXPECTED_SAMPLE_RATE: u32 = 16000;
 
+#[cfg(not(target_os = "emscripten"))]
+const USAGE: &str = "Usage: [--offline [--native] [--segment-seconds <seconds>] [--threads <count>]] [--dump-spectra <npy_or_csv_path>] <input_wav_path> <output_wav_path>
+       --pipeline <config_json_path> <input_wav_path> <output_wav_path>
+       spectrogram [--native] <input_wav_path> <output_png_path>
+       eval [--json] <clean_reference_wav_path> <processed_wav_path>
+       dataset [--native] [--seed <seed>] [--snr <db>]... --clean <wav_path>... --noise <wav_path>... <output_dir>";
+
 // Build sample program that uses the dtln_rs library
-// to process 16khz wav files for OSX.
-#[cfg(target_os = "macos")]
+// to process 16khz wav files.
+#[cfg(not(target_os = "emscripten"))]
 fn main() -> Result<()> {
-    // Check that there are two arguments
-    if std::env::args().count() != 3 {
-        println!("Usage: <input_wav_path> <output_wav_path>");
+    let mut offline = false;
+    let mut options = OfflineOptions::default();
+    // Set when an option only --offline uses is given.
+    let mut offline_options = false;
+    let mut dump_path: Option<String> = None;
+    let mut pipeline_path: Option<String> = None;
+    let mut paths = vec![];
+
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("spectrogram") => {
+            args.next();
+            return spectrogram(args);
+        }
+        Some("eval") => {
+            args.next();
+            return eval(args);
+        }
+        Some("dataset") => {
+            args.next();
+            return dataset(args);
+        }
+        _ => {}
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--offline" => offline = true,
+            "--native" => {
+                options.native = true;
+                offline_options = true;
+            }
+            "--segment-seconds" => {
+                let seconds: f32 = parse_next(&mut args);
+                options.segment_len = Some((seconds * EXPECTED_SAMPLE_RATE as f32) as usize);
+                offline_options = true;
+            }
+            "--threads" => {
+                options.threads = parse_next(&mut args);
+                offline_options = true;
+            }
+            "--dump-spectra" => dump_path = Some(parse_next(&mut args)),
+            "--pipeline" => pipeline_path = Some(parse_next(&mut args)),
+            _ => paths.push(arg),
+        }
+    }
+
+    // Check that there are two paths. Spectra are recorded from the real-time
+    // engine, offline segments run on engines of their own. Pipelines create
+    // their engines from the config, so the offline options would be ignored.
+    if paths.len() != 2
+        || (offline_options && !offline)
+        || (offline && dump_path.is_some())
+        || (pipeline_path.is_some() && (offline || dump_path.is_some()))
+    {
+        println!("{}", USAGE);
         std::process::exit(1);
     }
-    // Get input name as first argument
-    let input_name = std::env::args().nth(1).unwrap();
+    let input_name = &paths[0];
+    check_is_wav(input_name, true);
+    let output_name = &paths[1];
+    check_is_wav(output_name, false);
 
-    check_is_wav(&input_name, true);
+    let mut samples = vec![];
+    let sample_rate = read_wav_to_pcm32(input_name, &mut samples)?;
 
-    // Get output name as second argument
-    let output_name = std::env::args().nth(2).unwrap();
-    check_is_wav(&output_name, false);
+    if let Some(pipeline_path) = &pipeline_path {
+        let output = denoise_pipeline(&samples, sample_rate, pipeline_path)?;
+        write_pcm32_to_wav(output, output_name, sample_rate)?;
+        return Ok(());
+    }
+
+    if sample_rate != EXPECTED_SAMPLE_RATE {
+        return Err(anyhow!(
+            "{} is {}hz, expected {}hz",
+            input_name,
+            sample_rate,
+            EXPECTED_SAMPLE_RATE
+        ));
+    }
+
+    let output = if offline {
+        let result = denoise_offline(&samples, &options)?;
+        println!(
+            "Processed {:.2}s of audio in {:.2}s (realtime factor {:.4})",
+            samples.len() as f32 / EXPECTED_SAMPLE_RATE as f32,
+            result.elapsed.as_secs_f32(),
+            result.realtime_factor
+        );
+        result.samples
+    } else if let Some(dump_path) = &dump_path {
+        let recorder = SpectraRecorder::new();
+        let output = denoise_realtime(&samples, Some(Box::new(recorder.clone())))?;
+        recorder.write(dump_path)?;
+        println!(
+            "Wrote spectra of {} blocks to {}",
+            recorder.block_count(),
+            dump_path
+        );
+        output
+    } else {
+        denoise_realtime(&samples, None)?
+    };
+
+    // Write to wav
+    write_pcm32_to_wav(output, output_name, EXPECTED_SAMPLE_RATE)?;
+    Ok(())
+}
+
+// Render input, denoised output and removed noise spectrograms side by side.
+#[cfg(not(target_os = "emscripten"))]
+fn spectrogram(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut options = OfflineOptions::default();
+    let mut paths = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--native" => options.native = true,
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        println!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let input_name = &paths[0];
+    check_is_wav(input_name, true);
 
     let mut samples = vec![];
+    let sample_rate = read_wav_to_pcm32(input_name, &mut samples)?;
+    if sample_rate != EXPECTED_SAMPLE_RATE {
+        return Err(anyhow!(
+            "{} is {}hz, expected {}hz",
+            input_name,
+            sample_rate,
+            EXPECTED_SAMPLE_RATE
+        ));
+    }
+
+    // Offline output is time aligned, so the difference is what was removed.
+    let output = denoise_offline(&samples, &options)?.samples;
+    let removed = samples
+        .iter()
+        .zip(&output)
+        .map(|(input, output)| input - output)
+        .collect::<Vec<f32>>();
+
+    write_spectrogram_png(&[&samples, &output, &removed], &paths[1])?;
+    Ok(())
+}
+
+// Compare processed audio against the clean reference it should match.
+#[cfg(not(target_os = "emscripten"))]
+fn eval(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut json = false;
+    let mut paths = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        println!("{}", USAGE);
+        std::process::exit(1);
+    }
+    check_is_wav(&paths[0], true);
+    check_is_wav(&paths[1], true);
+
+    // The processed file may be the 32 bit float output of this CLI.
+    let mut reference = vec![];
+    let reference_rate = read_wav_to_pcm32(&paths[0], &mut reference)?;
+    let mut processed = vec![];
+    let processed_rate = read_wav_to_pcm32(&paths[1], &mut processed)?;
+    if reference_rate != EXPECTED_SAMPLE_RATE || processed_rate != EXPECTED_SAMPLE_RATE {
+        return Err(anyhow!(
+            "Both files must be {}hz, got {}hz and {}hz",
+            EXPECTED_SAMPLE_RATE,
+            reference_rate,
+            processed_rate
+        ));
+    }
+
+    let metrics = evaluate(&reference, &processed)?;
+    if json {
+        println!("{}", metrics.to_json());
+    } else {
+        print_metrics(&metrics);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "emscripten"))]
+fn print_metrics(metrics: &QualityMetrics) {
+    println!("SNR:                   {:8.3} dB", metrics.snr);
+    println!("SI-SDR:                {:8.3} dB", metrics.si_sdr);
+    println!("Segmental SNR:         {:8.3} dB", metrics.segmental_snr);
+    println!(
+        "Log-spectral distance: {:8.3} dB",
+        metrics.log_spectral_distance
+    );
+    println!("Intelligibility:       {:8.3}", metrics.intelligibility);
+}
+
+// Synthesize a clean/noisy dataset, then evaluate the engine over it.
+#[cfg(not(target_os = "emscripten"))]
+fn dataset(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut synthesis = SynthesisOptions::default();
+    let mut snrs_db = vec![];
+    let mut offline = OfflineOptions::default();
+    let mut clean_paths = vec![];
+    let mut noise_paths = vec![];
+    let mut paths = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--native" => offline.native = true,
+            "--seed" => synthesis.seed = parse_next(&mut args),
+            "--snr" => snrs_db.push(parse_next(&mut args)),
+            "--clean" => clean_paths.push(parse_next(&mut args)),
+            "--noise" => noise_paths.push(parse_next(&mut args)),
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 1 || clean_paths.is_empty() || noise_paths.is_empty() {
+        println!("{}", USAGE);
+        std::process::exit(1);
+    }
+    if !snrs_db.is_empty() {
+        synthesis.snrs_db = snrs_db;
+    }
+    let output_dir = &paths[0];
+
+    let entries = synthesize_dataset(&clean_paths, &noise_paths, output_dir, &synthesis)?;
+    println!("Wrote {} pairs to {}", entries.len(), output_dir);
+
+    let evaluations = evaluate_dataset(output_dir, &offline)?;
+    for evaluation in &evaluations {
+        if let Some(error) = &evaluation.error {
+            println!("Unable to evaluate {}: {}", evaluation.id, error);
+        }
+    }
+    let results_path = std::path::Path::new(output_dir).join("results.json");
+    std::fs::write(&results_path, evaluations_to_json(&evaluations))?;
+
+    let (noisy, denoised) = mean_metrics(&evaluations);
+    println!("Noisy:");
+    print_metrics(&noisy);
+    println!("Denoised:");
+    print_metrics(&denoised);
+    println!("Wrote per pair results to {}", results_path.display());
+    Ok(())
+}
+
+// Feed the processor in real-time sized blocks, the way a live audio source would.
+#[cfg(not(target_os = "emscripten"))]
+fn denoise_realtime(samples: &[f32], observer: Option<Box<dyn BlockObserver>>) -> Result<Vec<f32>> {
     let mut output = vec![];
-    let _sample_rate = read_wav_to_pcm32(&input_name, &mut samples);
     let mut processor = DtlnDeferredProcessor::new()?;
+    processor.engine.lock().unwrap().set_observer(observer);
 
     // Simulate blocked input for every 16834 samples
     for i in (0..samples.len()).step_by(BLOCK_SIZE) {
@@ -56,12 +329,45 @@ fn main() -> Result<()> {
     }
     processor.stop();
 
-    // Write to wav
-    write_pcm32_to_wav(output, &output_name, EXPECTED_SAMPLE_RATE)?;
-    Ok(())
+    Ok(output)
+}
+
+// Run a configured pipeline over the whole file, compensating its latency so
+// the output lines up with the input.
+#[cfg(not(target_os = "emscripten"))]
+fn denoise_pipeline(samples: &[f32], sample_rate: u32, config_path: &str) -> Result<Vec<f32>> {
+    let mut pipeline = AudioPipeline::from_file(config_path)?;
+    if pipeline.sample_rate() != sample_rate {
+        return Err(anyhow!(
+            "Input is {} Hz but the pipeline expects {} Hz",
+            sample_rate,
+            pipeline.sample_rate()
+        ));
+    }
+
+    let latency = pipeline.latency();
+    let mut input = samples.to_vec();
+    input.resize(samples.len() + latency, 0.0);
+    let mut output = vec![0.0; input.len()];
+    for (input, output) in input.chunks(BLOCK_SIZE).zip(output.chunks_mut(BLOCK_SIZE)) {
+        pipeline.process(input, output);
+    }
+    output.drain(..latency);
+    Ok(output)
+}
+
+#[cfg(not(target_os = "emscripten"))]
+fn parse_next<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    match args.next().map(|value| value.parse()) {
+        Some(Ok(value)) => value,
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(not(target_os = "emscripten"))]
 fn check_is_wav(name: &str, check_exists: bool) {
     let path = std::path::Path::new(name);
 
@@ -0,0 +1,578 @@
+// Primary export functions for the NEON module.
+use crate::audio_pipeline::AudioPipeline;
+use crate::dtln_agc::AgcConfig;
+use crate::dtln_filters::PreFilterConfig;
+use crate::dtln_limiter::LimiterConfig;
+use crate::dtln_offline::{denoise_file, denoise_offline, OfflineOptions};
+use crate::dtln_processor::{DtlnProcessor, ProcessorMode, ProcessorOptions};
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use neon::prelude::*;
+
+use neon::types::buffer::TypedArray;
+use neon::types::JsPromise;
+
+// DTLN sample rate.
+const SAMPLE_RATE: f64 = 16000.0;
+
+/**
+* Create a denoiser.
+*
+* @param {object} options - Optional, any of:
+* mode: "deferred" (the default) denoises on a background thread and returns
+* the output of the previous call, "immediate" denoises on the calling thread.
+* sampleRate: Rate of the samples, 16000 by default. Other rates are resampled.
+* threads: TFLite interpreter threads, 1 by default.
+* native: Use the native model implementation instead of TFLite.
+* minGainDb: Suppression level, see dtln_set_min_gain_db.
+*/
+fn dtln_create_napi(mut cx: FunctionContext) -> JsResult<JsBox<Arc<Mutex<DtlnProcessor>>>> {
+    let mut options = ProcessorOptions::default();
+    if let Some(object) = cx.argument_opt(0) {
+        if object.is_a::<JsObject, _>(&mut cx) {
+            let object = object.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            if let Some(mode) = object.get_opt::<JsString, _, _>(&mut cx, "mode")? {
+                options.mode = match mode.value(&mut cx).as_str() {
+                    "immediate" => ProcessorMode::Immediate,
+                    "deferred" => ProcessorMode::Deferred,
+                    mode => return cx.throw_error(format!("Unknown mode {}", mode)),
+                };
+            }
+            if let Some(value) = optional_number(&mut cx, object, "sampleRate")? {
+                options.sample_rate = value as u32;
+            }
+            if let Some(value) = optional_number(&mut cx, object, "threads")? {
+                options.threads = value as usize;
+            }
+            if let Some(value) = object.get_opt::<JsBoolean, _, _>(&mut cx, "native")? {
+                options.native = value.value(&mut cx);
+            }
+            options.min_gain_db = optional_number(&mut cx, object, "minGainDb")?;
+        }
+    }
+
+    match DtlnProcessor::new(options) {
+        Ok(dtln_processor) => Ok(cx.boxed(Arc::new(Mutex::new(dtln_processor)))),
+        Err(e) => cx.throw_error(format!("Failed to create DtlnProcessor: {:#}", e)),
+    }
+}
+
+/**
+* Stop the processor. Exported as both dtln_destroy, matching WASM, and
+* dtln_stop. Calling it more than once has no effect.
+*/
+fn dtln_destroy_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    dtln_processor.lock().unwrap().stop();
+    Ok(cx.undefined())
+}
+
+/**
+* Denoise the samples.
+*
+* @param {Float32Array} samples - The samples to denoise.
+* @param {Float32Array} output - The denoised samples.
+
+* @returns {boolean} - True if the processing thread is backed up.
+*/
+fn dtln_denoise_napi(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    if cx.len() != 3 {
+        return cx.throw_error("Invalid number of arguments, expected <engine: JsBox, samples: Float32Array, output: Float32Array>");
+    }
+
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    let samples = cx.argument::<JsTypedArray<f32>>(1)?;
+    let mut output = cx.argument::<JsTypedArray<f32>>(2)?;
+
+    let result = {
+        let lock = cx.lock();
+        // Bound to a local so the borrows end before the lock does.
+        let result = match (samples.try_borrow(&lock), output.try_borrow_mut(&lock)) {
+            // The processor panics if its thread failed.
+            (Ok(samples_slice), Ok(mut output_slice)) => catch_errors(|| {
+                dtln_processor
+                    .lock()
+                    .unwrap()
+                    .denoise(&samples_slice, &mut output_slice)
+            }),
+            _ => Err("samples and output must not overlap".to_string()),
+        };
+        result
+    };
+
+    match result {
+        Ok(processor_starved) => Ok(cx.boolean(processor_starved)),
+        Err(e) => cx.throw_error(format!("Error in dtln_denoise: {}", e)),
+    }
+}
+
+/**
+* Set the minimum gain of the suppression mask.
+*
+* @param {number} minGainDb - Minimum gain in dB, -Infinity for full suppression.
+*/
+fn dtln_set_min_gain_db_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    let min_gain_db = cx.argument::<JsNumber>(1)?.value(&mut cx) as f32;
+    dtln_processor
+        .lock()
+        .unwrap()
+        .engine
+        .lock()
+        .unwrap()
+        .set_min_gain_db(min_gain_db);
+    Ok(cx.undefined())
+}
+
+/**
+* Set the dry/wet mix.
+*
+* @param {number} wet - 1.0 outputs only the denoised signal, 0.0 only the input.
+*/
+fn dtln_set_mix_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    let wet = cx.argument::<JsNumber>(1)?.value(&mut cx) as f32;
+    dtln_processor
+        .lock()
+        .unwrap()
+        .engine
+        .lock()
+        .unwrap()
+        .set_mix(wet);
+    Ok(cx.undefined())
+}
+
+/**
+* Enable automatic gain control of the denoised output.
+*
+* @param {object|null} options - null disables the AGC. Otherwise any of
+* targetLevelDb, maxGainDb, attackMs, releaseMs and vadGated, the others keep
+* their defaults.
+*/
+fn dtln_set_agc_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+
+    let config = match cx.argument_opt(1) {
+        Some(options) if options.is_a::<JsObject, _>(&mut cx) => {
+            let options = options.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            let mut config = AgcConfig::default();
+            if let Some(value) = optional_number(&mut cx, options, "targetLevelDb")? {
+                config.target_level_db = value;
+            }
+            if let Some(value) = optional_number(&mut cx, options, "maxGainDb")? {
+                config.max_gain_db = value;
+            }
+            if let Some(value) = optional_number(&mut cx, options, "attackMs")? {
+                config.attack_ms = value;
+            }
+            if let Some(value) = optional_number(&mut cx, options, "releaseMs")? {
+                config.release_ms = value;
+            }
+            if let Some(value) = options.get_opt::<JsBoolean, _, _>(&mut cx, "vadGated")? {
+                config.vad_gated = value.value(&mut cx);
+            }
+            Some(config)
+        }
+        _ => None,
+    };
+
+    dtln_processor
+        .lock()
+        .unwrap()
+        .engine
+        .lock()
+        .unwrap()
+        .set_agc(config);
+    Ok(cx.undefined())
+}
+
+/**
+* Enable the look-ahead peak limiter on the output.
+*
+* @param {object|null} options - null disables the limiter. Otherwise any of
+* ceilingDb, lookaheadMs and releaseMs, the others keep their defaults.
+*/
+fn dtln_set_limiter_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+
+    let config = match cx.argument_opt(1) {
+        Some(options) if options.is_a::<JsObject, _>(&mut cx) => {
+            let options = options.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            let mut config = LimiterConfig::default();
+            if let Some(value) = optional_number(&mut cx, options, "ceilingDb")? {
+                config.ceiling_db = value;
+            }
+            if let Some(value) = optional_number(&mut cx, options, "lookaheadMs")? {
+                config.lookahead_ms = value;
+            }
+            if let Some(value) = optional_number(&mut cx, options, "releaseMs")? {
+                config.release_ms = value;
+            }
+            Some(config)
+        }
+        _ => None,
+    };
+
+    dtln_processor
+        .lock()
+        .unwrap()
+        .engine
+        .lock()
+        .unwrap()
+        .set_limiter(config);
+    Ok(cx.undefined())
+}
+
+/**
+* Clipping counters of the output.
+*
+* @param {boolean} reset - Reset the counters after reading them.
+* @returns {{clippedSamples: number, limitedSamples: number}}
+*/
+fn dtln_clip_stats_napi(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    let reset = match cx.argument_opt(1) {
+        Some(reset) => reset
+            .downcast_or_throw::<JsBoolean, _>(&mut cx)?
+            .value(&mut cx),
+        None => false,
+    };
+
+    let stats = {
+        let processor = dtln_processor.lock().unwrap();
+        let mut engine = processor.engine.lock().unwrap();
+        let stats = engine.clip_stats();
+        if reset {
+            engine.reset_clip_stats();
+        }
+        stats
+    };
+
+    let result = cx.empty_object();
+    let clipped_samples = cx.number(stats.clipped_samples as f64);
+    result.set(&mut cx, "clippedSamples", clipped_samples)?;
+    let limited_samples = cx.number(stats.limited_samples as f64);
+    result.set(&mut cx, "limitedSamples", limited_samples)?;
+    Ok(result)
+}
+
+/**
+* Configure the filters run on the input before the model.
+*
+* @param {{dcBlocker?: boolean, highPassHz?: number|null}} options - Omitted or
+* null fields disable the filter.
+*/
+fn dtln_set_pre_filter_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+
+    let mut config = PreFilterConfig::default();
+    if let Some(options) = cx.argument_opt(1) {
+        if options.is_a::<JsObject, _>(&mut cx) {
+            let options = options.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            if let Some(value) = options.get_opt::<JsBoolean, _, _>(&mut cx, "dcBlocker")? {
+                config.dc_blocker = value.value(&mut cx);
+            }
+            config.high_pass_hz = optional_number(&mut cx, options, "highPassHz")?;
+        }
+    }
+
+    dtln_processor
+        .lock()
+        .unwrap()
+        .engine
+        .lock()
+        .unwrap()
+        .set_pre_filter(config);
+    Ok(cx.undefined())
+}
+
+fn optional_number(
+    cx: &mut FunctionContext,
+    object: Handle<JsObject>,
+    key: &str,
+) -> NeonResult<Option<f32>> {
+    Ok(object
+        .get_opt::<JsNumber, _, _>(cx, key)?
+        .map(|value| value.value(cx) as f32))
+}
+
+/**
+* Voice activity of the samples returned by the most recent dtln_denoise call.
+*
+* @returns {{speechProbability: number, isSpeech: boolean}}
+*/
+fn dtln_vad_napi(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    let vad = dtln_processor.lock().unwrap().last_vad();
+
+    let result = cx.empty_object();
+    let speech_probability = cx.number(vad.speech_probability);
+    result.set(&mut cx, "speechProbability", speech_probability)?;
+    let is_speech = cx.boolean(vad.is_speech);
+    result.set(&mut cx, "isSpeech", is_speech)?;
+    Ok(result)
+}
+
+/**
+* Processing statistics since creation or the last reset.
+*
+* @param {boolean} reset - Reset the statistics after reading them.
+* @returns {{calls: number, samples: number, starvedCalls: number,
+* processingTimeMs: number, maxProcessingTimeMs: number, realtimeFactor:
+* number}} - realtimeFactor is the processing time divided by the duration of
+* the samples, below 1.0 when faster than real time.
+*/
+fn dtln_stats_napi(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    let reset = match cx.argument_opt(1) {
+        Some(reset) => reset
+            .downcast_or_throw::<JsBoolean, _>(&mut cx)?
+            .value(&mut cx),
+        None => false,
+    };
+
+    let (stats, sample_rate) = {
+        let mut processor = dtln_processor.lock().unwrap();
+        let stats = processor.stats();
+        if reset {
+            processor.reset_stats();
+        }
+        (stats, processor.options().sample_rate)
+    };
+
+    let audio_seconds = stats.samples as f64 / sample_rate as f64;
+    let processing_seconds = stats.processing_time.as_secs_f64();
+    let realtime_factor = if audio_seconds > 0.0 {
+        processing_seconds / audio_seconds
+    } else {
+        0.0
+    };
+
+    let result = cx.empty_object();
+    let calls = cx.number(stats.calls as f64);
+    result.set(&mut cx, "calls", calls)?;
+    let samples = cx.number(stats.samples as f64);
+    result.set(&mut cx, "samples", samples)?;
+    let starved_calls = cx.number(stats.starved_calls as f64);
+    result.set(&mut cx, "starvedCalls", starved_calls)?;
+    let processing_time = cx.number(processing_seconds * 1000.0);
+    result.set(&mut cx, "processingTimeMs", processing_time)?;
+    let max_processing_time = cx.number(stats.max_processing_time.as_secs_f64() * 1000.0);
+    result.set(&mut cx, "maxProcessingTimeMs", max_processing_time)?;
+    let realtime_factor = cx.number(realtime_factor);
+    result.set(&mut cx, "realtimeFactor", realtime_factor)?;
+    Ok(result)
+}
+
+/**
+* Discard buffered audio and return the denoiser to its initial state, e.g.
+* before an unrelated stream. Settings and statistics are kept.
+*/
+fn dtln_reset_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    dtln_processor.lock().unwrap().reset();
+    Ok(cx.undefined())
+}
+
+/**
+* Delay of the output in samples at the denoiser sample rate. In deferred mode
+* the output is also one dtln_denoise call behind, which is not included.
+*/
+fn dtln_latency_napi(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnProcessor>>>>(0)?;
+    let latency = dtln_processor.lock().unwrap().latency();
+    Ok(cx.number(latency as f64))
+}
+
+// Run work which may fail or panic, e.g. on the libuv threadpool, returning
+// the message to throw or reject a Promise with.
+fn catch_errors<T>(work: impl FnOnce() -> anyhow::Result<T>) -> std::result::Result<T, String> {
+    match catch_unwind(AssertUnwindSafe(work)) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(format!("{:#}", e)),
+        Err(_) => Err("Panic while denoising".to_string()),
+    }
+}
+
+// Options of the whole buffer and file functions, from an optional object
+// with native, segmentSeconds and threads.
+fn offline_options(cx: &mut FunctionContext, index: i32) -> NeonResult<OfflineOptions> {
+    let mut options = OfflineOptions::default();
+    if let Some(object) = cx.argument_opt(index) {
+        if object.is_a::<JsObject, _>(cx) {
+            let object = object.downcast_or_throw::<JsObject, _>(cx)?;
+            if let Some(value) = object.get_opt::<JsBoolean, _, _>(cx, "native")? {
+                options.native = value.value(cx);
+            }
+            if let Some(value) = optional_number(cx, object, "segmentSeconds")? {
+                options.segment_len = Some((value as f64 * SAMPLE_RATE) as usize);
+            }
+            if let Some(value) = optional_number(cx, object, "threads")? {
+                options.threads = value as usize;
+            }
+        }
+    }
+    Ok(options)
+}
+
+// Neon 0.10 can't create typed arrays directly, so the samples are copied into
+// an ArrayBuffer and a Float32Array is constructed over it.
+fn float32_array<'a, C: Context<'a>>(
+    cx: &mut C,
+    samples: &[f32],
+) -> JsResult<'a, JsTypedArray<f32>> {
+    let mut buffer = JsArrayBuffer::new(cx, std::mem::size_of_val(samples))?;
+    for (bytes, sample) in buffer
+        .as_mut_slice(cx)
+        .chunks_exact_mut(std::mem::size_of::<f32>())
+        .zip(samples)
+    {
+        bytes.copy_from_slice(&sample.to_ne_bytes());
+    }
+    let constructor = cx.global().get::<JsFunction, _, _>(cx, "Float32Array")?;
+    let array = constructor.construct(cx, [buffer.upcast::<JsValue>()])?;
+    array.downcast_or_throw(cx)
+}
+
+/**
+* Denoise a whole 16khz buffer on the libuv threadpool. The output is time
+* aligned with the input.
+*
+* @param {Float32Array} samples - The samples to denoise.
+* @param {object} options - Optional, any of native, segmentSeconds and threads.
+* @returns {Promise<Float32Array>} - Rejected if denoising fails.
+*/
+fn dtln_denoise_buffer_napi(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let samples = cx.argument::<JsTypedArray<f32>>(0)?;
+    let samples = samples.as_slice(&cx).to_vec();
+    let options = offline_options(&mut cx, 1)?;
+
+    let promise = cx
+        .task(move || catch_errors(|| denoise_offline(&samples, &options)))
+        .promise(|mut cx, result| match result {
+            Ok(result) => float32_array(&mut cx, &result.samples),
+            Err(e) => cx.throw_error(e),
+        });
+    Ok(promise)
+}
+
+/**
+* Denoise a 16khz 16 bit mono wav file into a 32 bit float wav file, on the
+* libuv threadpool.
+*
+* @param {string} path - The file to denoise.
+* @param {string} outPath - The file to write.
+* @param {object} options - Optional, any of native, segmentSeconds and threads.
+* @returns {Promise<{durationSeconds: number, realtimeFactor: number}>} -
+* Rejected if the file can't be read or written.
+*/
+fn dtln_denoise_file_napi(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let path = cx.argument::<JsString>(0)?.value(&mut cx);
+    let out_path = cx.argument::<JsString>(1)?.value(&mut cx);
+    let options = offline_options(&mut cx, 2)?;
+
+    let promise = cx
+        .task(move || catch_errors(|| denoise_file(&path, &out_path, &options)))
+        .promise(|mut cx, result| match result {
+            Ok(result) => {
+                let object = cx.empty_object();
+                let duration = cx.number(result.samples.len() as f64 / SAMPLE_RATE);
+                object.set(&mut cx, "durationSeconds", duration)?;
+                let realtime_factor = cx.number(result.realtime_factor);
+                object.set(&mut cx, "realtimeFactor", realtime_factor)?;
+                Ok(object)
+            }
+            Err(e) => cx.throw_error(e),
+        });
+    Ok(promise)
+}
+
+impl Finalize for AudioPipeline {}
+
+/**
+* Create an audio pipeline from a JSON config, see audio_pipeline.rs.
+*
+* @param {string} config - The pipeline config as JSON.
+*/
+fn dtln_pipeline_create_napi(mut cx: FunctionContext) -> JsResult<JsBox<Arc<Mutex<AudioPipeline>>>> {
+    let config = cx.argument::<JsString>(0)?.value(&mut cx);
+    match AudioPipeline::from_json(&config) {
+        Ok(pipeline) => Ok(cx.boxed(Arc::new(Mutex::new(pipeline)))),
+        Err(e) => cx.throw_error(format!("Failed to create pipeline: {:#}", e)),
+    }
+}
+
+/**
+* Process samples at the pipeline sample rate.
+*
+* @param {Float32Array} samples - The samples to process, of any length.
+* @param {Float32Array} output - Receives samples.length processed samples,
+* delayed by dtln_pipeline_latency.
+*/
+fn dtln_pipeline_process_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let pipeline = cx.argument::<JsBox<Arc<Mutex<AudioPipeline>>>>(0)?;
+    let samples = cx.argument::<JsTypedArray<f32>>(1)?;
+    let mut output = cx.argument::<JsTypedArray<f32>>(2)?;
+
+    let result = {
+        let lock = cx.lock();
+        // Copied first, so samples and output may be the same array.
+        let samples_slice = samples.try_borrow(&lock).map(|slice| slice.to_vec());
+        let result = match (samples_slice, output.try_borrow_mut(&lock)) {
+            (Ok(samples_slice), Ok(mut output_slice)) => {
+                if output_slice.len() < samples_slice.len() {
+                    Err("Output buffer too small".to_string())
+                } else {
+                    pipeline
+                        .lock()
+                        .unwrap()
+                        .process(&samples_slice, &mut output_slice);
+                    Ok(())
+                }
+            }
+            _ => Err("Unable to borrow samples or output".to_string()),
+        };
+        result
+    };
+
+    match result {
+        Ok(()) => Ok(cx.undefined()),
+        Err(e) => cx.throw_error(format!("Error in dtln_pipeline_process: {}", e)),
+    }
+}
+
+/**
+* Delay of the pipeline output in samples, at the pipeline sample rate.
+*/
+fn dtln_pipeline_latency_napi(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let pipeline = cx.argument::<JsBox<Arc<Mutex<AudioPipeline>>>>(0)?;
+    let latency = pipeline.lock().unwrap().latency();
+    Ok(cx.number(latency as f64))
+}
+
+#[neon::main]
+fn main(mut cx: ModuleContext) -> NeonResult<()> {
+    cx.export_function("dtln_denoise", dtln_denoise_napi)?;
+    cx.export_function("dtln_create", dtln_create_napi)?;
+    cx.export_function("dtln_destroy", dtln_destroy_napi)?;
+    cx.export_function("dtln_stop", dtln_destroy_napi)?;
+    cx.export_function("dtln_set_min_gain_db", dtln_set_min_gain_db_napi)?;
+    cx.export_function("dtln_set_mix", dtln_set_mix_napi)?;
+    cx.export_function("dtln_vad", dtln_vad_napi)?;
+    cx.export_function("dtln_set_agc", dtln_set_agc_napi)?;
+    cx.export_function("dtln_set_limiter", dtln_set_limiter_napi)?;
+    cx.export_function("dtln_clip_stats", dtln_clip_stats_napi)?;
+    cx.export_function("dtln_set_pre_filter", dtln_set_pre_filter_napi)?;
+    cx.export_function("dtln_stats", dtln_stats_napi)?;
+    cx.export_function("dtln_reset", dtln_reset_napi)?;
+    cx.export_function("dtln_latency", dtln_latency_napi)?;
+    cx.export_function("dtln_denoise_buffer", dtln_denoise_buffer_napi)?;
+    cx.export_function("dtln_denoise_file", dtln_denoise_file_napi)?;
+    cx.export_function("dtln_pipeline_create", dtln_pipeline_create_napi)?;
+    cx.export_function("dtln_pipeline_process", dtln_pipeline_process_napi)?;
+    cx.export_function("dtln_pipeline_latency", dtln_pipeline_latency_napi)?;
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+// Linear parameter ramps, used so that parameters changed while audio is
+// running glide to their new value instead of jumping (zipper noise).
+
+#[derive(Clone, Copy)]
+pub struct ParamRamp {
+    current: f32,
+    target: f32,
+    step: f32,
+    ramp_len: u32,
+    // advance() calls left until the target is reached.
+    remaining: u32,
+}
+
+impl ParamRamp {
+    /**
+     * @param value Initial value, reached immediately.
+     * @param ramp_len Number of advance() calls a change takes to complete.
+     */
+    pub fn new(value: f32, ramp_len: u32) -> Self {
+        ParamRamp {
+            current: value,
+            target: value,
+            step: 0.0,
+            ramp_len: ramp_len.max(1),
+            remaining: 0,
+        }
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    /**
+     * The value last returned by advance().
+     */
+    pub fn value(&self) -> f32 {
+        self.current
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target;
+        self.step = (target - self.current) / self.ramp_len as f32;
+        self.remaining = self.ramp_len;
+    }
+
+    /**
+     * Advance the ramp by one step and return the new value.
+     */
+    pub fn advance(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            // Counted rather than compared, so rounding never adds a step.
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp_reaches_target() {
+        let floor = 10f32.powf(-12.0 / 20.0);
+        for (start, target, ramp_len) in [
+            (0.0, 1.0, 512),
+            (1.0, 0.0, 512),
+            (0.0, floor, 4),
+            (floor, 0.0, 4),
+            (0.1, 0.7, 3),
+        ] {
+            let mut ramp = ParamRamp::new(start, ramp_len);
+            ramp.set_target(target);
+            for _ in 1..ramp_len {
+                assert_ne!(ramp.advance(), target);
+            }
+            assert_eq!(ramp.advance(), target);
+            assert_eq!(ramp.advance(), target);
+        }
+    }
+}
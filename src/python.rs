@@ -0,0 +1,309 @@
+// Python bindings, built into a wheel by maturin with the python feature, see
+// pyproject.toml. Samples are passed as float32 NumPy arrays and the GIL is
+// released while audio is processed.
+
+// The pyo3 0.22 macros convert returned errors into PyErr even when they
+// already are one, which clippy reports at the PyResult return types.
+#![allow(clippy::useless_conversion)]
+
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de::DeserializeOwned;
+
+use crate::audio_pipeline::AudioPipeline;
+use crate::constants::DTLN_BLOCK_SHIFT;
+use crate::dtln_agc::AgcConfig;
+use crate::dtln_engine::DtlnEngine;
+use crate::dtln_filters::PreFilterConfig;
+use crate::dtln_limiter::LimiterConfig;
+use crate::dtln_offline::{denoise_offline, OfflineOptions};
+use crate::dtln_processor::{DtlnDeferredProcessor, DtlnImmediateProcessor, DtlnProcessEngine};
+
+// DTLN sample rate.
+const SAMPLE_RATE: u32 = 16000;
+
+// Serialize a Python object with the json module and parse it as T, so
+// dictionaries use the field names of the JSON pipeline configs.
+fn from_python<T: DeserializeOwned>(object: &Bound<'_, PyAny>) -> PyResult<T> {
+    let json = match object.extract::<String>() {
+        Ok(json) => json,
+        Err(_) => object
+            .py()
+            .import_bound("json")?
+            .call_method1("dumps", (object,))?
+            .extract()?,
+    };
+    serde_json::from_str(&json).map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+}
+
+fn create_engine(native: bool) -> PyResult<DtlnEngine> {
+    let engine = if native {
+        DtlnEngine::new_native()
+    } else {
+        DtlnEngine::new()
+    };
+    engine.ok_or_else(|| pyo3::exceptions::PyRuntimeError::new_err("Failed to create DtlnEngine"))
+}
+
+fn vad_dict<'py>(py: Python<'py>, engine: &DtlnEngine) -> PyResult<Bound<'py, PyDict>> {
+    let vad = engine.vad_result();
+    let result = PyDict::new_bound(py);
+    result.set_item("speech_probability", vad.speech_probability)?;
+    result.set_item("is_speech", vad.is_speech)?;
+    Ok(result)
+}
+
+/**
+ * Streaming denoiser at 16khz. Input of any length is buffered, and every
+ * call returns the output of the DTLN_BLOCK_SHIFT blocks completed so far,
+ * delayed by latency samples.
+ */
+#[pyclass(name = "DtlnEngine", module = "dtln_rs")]
+struct PyDtlnEngine {
+    engine: DtlnEngine,
+    // Input waiting for a whole block.
+    pending: Vec<f32>,
+}
+
+#[pymethods]
+impl PyDtlnEngine {
+    #[new]
+    #[pyo3(signature = (native = false))]
+    fn new(native: bool) -> PyResult<Self> {
+        Ok(PyDtlnEngine {
+            engine: create_engine(native)?,
+            pending: Vec::new(),
+        })
+    }
+
+    fn process<'py>(
+        &mut self,
+        py: Python<'py>,
+        samples: PyReadonlyArray1<'py, f32>,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        self.pending.extend_from_slice(samples.as_slice()?);
+        let complete = self.pending.len() / DTLN_BLOCK_SHIFT * DTLN_BLOCK_SHIFT;
+        let input = self.pending.drain(..complete).collect::<Vec<f32>>();
+
+        let engine = &mut self.engine;
+        let output = py.allow_threads(|| {
+            let mut output = vec![0.0; input.len()];
+            engine.denoise(&input, &mut output);
+            output
+        });
+        Ok(output.into_pyarray_bound(py))
+    }
+
+    #[getter]
+    fn latency(&self) -> usize {
+        self.engine.latency()
+    }
+
+    fn set_min_gain_db(&mut self, min_gain_db: f32) {
+        self.engine.set_min_gain_db(min_gain_db);
+    }
+
+    fn set_mix(&mut self, wet: f32) {
+        self.engine.set_mix(wet);
+    }
+
+    #[pyo3(signature = (dc_blocker = false, high_pass_hz = None))]
+    fn set_pre_filter(&mut self, dc_blocker: bool, high_pass_hz: Option<f32>) {
+        self.engine.set_pre_filter(PreFilterConfig {
+            dc_blocker,
+            high_pass_hz,
+        });
+    }
+
+    /**
+     * Enable the AGC with a dict of AgcConfig fields, or disable it with None.
+     */
+    #[pyo3(signature = (config))]
+    fn set_agc(&mut self, config: Option<&Bound<'_, PyAny>>) -> PyResult<()> {
+        let config = config.map(from_python::<AgcConfig>).transpose()?;
+        self.engine.set_agc(config);
+        Ok(())
+    }
+
+    /**
+     * Enable the limiter with a dict of LimiterConfig fields, or disable it
+     * with None.
+     */
+    #[pyo3(signature = (config))]
+    fn set_limiter(&mut self, config: Option<&Bound<'_, PyAny>>) -> PyResult<()> {
+        let config = config.map(from_python::<LimiterConfig>).transpose()?;
+        self.engine.set_limiter(config);
+        Ok(())
+    }
+
+    /**
+     * Voice activity of the blocks processed by the last process() call.
+     */
+    fn vad<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        vad_dict(py, &self.engine)
+    }
+
+    #[pyo3(signature = (reset = false))]
+    fn clip_stats<'py>(&mut self, py: Python<'py>, reset: bool) -> PyResult<Bound<'py, PyDict>> {
+        let stats = self.engine.clip_stats();
+        if reset {
+            self.engine.reset_clip_stats();
+        }
+        let result = PyDict::new_bound(py);
+        result.set_item("clipped_samples", stats.clipped_samples)?;
+        result.set_item("limited_samples", stats.limited_samples)?;
+        Ok(result)
+    }
+}
+
+/**
+ * DtlnImmediateProcessor, denoising each call synchronously. Only whole
+ * DTLN_BLOCK_SHIFT blocks of the input are denoised.
+ */
+#[pyclass(name = "ImmediateProcessor", module = "dtln_rs")]
+struct PyImmediateProcessor {
+    processor: DtlnImmediateProcessor,
+}
+
+#[pymethods]
+impl PyImmediateProcessor {
+    #[new]
+    fn new() -> PyResult<Self> {
+        Ok(PyImmediateProcessor {
+            processor: DtlnImmediateProcessor::new()?,
+        })
+    }
+
+    fn denoise<'py>(
+        &mut self,
+        py: Python<'py>,
+        samples: PyReadonlyArray1<'py, f32>,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let samples = samples.as_slice()?;
+        let processor = &mut self.processor;
+        let result = py.allow_threads(|| processor.denoise(samples))?;
+        Ok(result.samples.into_pyarray_bound(py))
+    }
+}
+
+/**
+ * DtlnDeferredProcessor, denoising on a background thread. Every call returns
+ * the output of the previous one, together with whether the thread fell
+ * behind.
+ */
+#[pyclass(name = "DeferredProcessor", module = "dtln_rs")]
+struct PyDeferredProcessor {
+    processor: DtlnDeferredProcessor,
+}
+
+#[pymethods]
+impl PyDeferredProcessor {
+    #[new]
+    fn new() -> PyResult<Self> {
+        Ok(PyDeferredProcessor {
+            processor: DtlnDeferredProcessor::new()?,
+        })
+    }
+
+    fn denoise<'py>(
+        &mut self,
+        py: Python<'py>,
+        samples: PyReadonlyArray1<'py, f32>,
+    ) -> PyResult<(Bound<'py, PyArray1<f32>>, bool)> {
+        let samples = samples.as_slice()?;
+        let processor = &mut self.processor;
+        let result = py.allow_threads(|| processor.denoise(samples))?;
+        Ok((
+            result.samples.into_pyarray_bound(py),
+            result.processor_starved,
+        ))
+    }
+
+    fn stop(&mut self, py: Python<'_>) {
+        let processor = &mut self.processor;
+        py.allow_threads(|| processor.stop());
+    }
+}
+
+/**
+ * AudioPipeline built from a config dict or JSON string. Every call returns
+ * as many samples as it is given, delayed by latency samples.
+ */
+#[pyclass(name = "Pipeline", module = "dtln_rs")]
+struct PyPipeline {
+    pipeline: AudioPipeline,
+}
+
+#[pymethods]
+impl PyPipeline {
+    #[new]
+    fn new(config: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Ok(PyPipeline {
+            pipeline: AudioPipeline::new(&from_python(config)?)?,
+        })
+    }
+
+    fn process<'py>(
+        &mut self,
+        py: Python<'py>,
+        samples: PyReadonlyArray1<'py, f32>,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let samples = samples.as_slice()?;
+        let pipeline = &mut self.pipeline;
+        let output = py.allow_threads(|| {
+            let mut output = vec![0.0; samples.len()];
+            pipeline.process(samples, &mut output);
+            output
+        });
+        Ok(output.into_pyarray_bound(py))
+    }
+
+    #[getter]
+    fn latency(&self) -> usize {
+        self.pipeline.latency()
+    }
+
+    #[getter]
+    fn sample_rate(&self) -> u32 {
+        self.pipeline.sample_rate()
+    }
+}
+
+/**
+ * Denoise a whole 16khz recording. The output is time aligned with the input.
+ */
+#[pyfunction]
+#[pyo3(signature = (samples, native = false, segment_seconds = None, threads = None))]
+fn denoise<'py>(
+    py: Python<'py>,
+    samples: PyReadonlyArray1<'py, f32>,
+    native: bool,
+    segment_seconds: Option<f32>,
+    threads: Option<usize>,
+) -> PyResult<Bound<'py, PyArray1<f32>>> {
+    let mut options = OfflineOptions {
+        native,
+        segment_len: segment_seconds.map(|seconds| (seconds * SAMPLE_RATE as f32) as usize),
+        ..OfflineOptions::default()
+    };
+    if let Some(threads) = threads {
+        options.threads = threads;
+    }
+
+    let samples = samples.as_slice()?;
+    let result = py.allow_threads(|| denoise_offline(samples, &options))?;
+    Ok(result.samples.into_pyarray_bound(py))
+}
+
+#[pymodule]
+fn dtln_rs(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("SAMPLE_RATE", SAMPLE_RATE)?;
+    module.add("BLOCK_SHIFT", DTLN_BLOCK_SHIFT)?;
+    module.add_class::<PyDtlnEngine>()?;
+    module.add_class::<PyImmediateProcessor>()?;
+    module.add_class::<PyDeferredProcessor>()?;
+    module.add_class::<PyPipeline>()?;
+    module.add_function(wrap_pyfunction!(denoise, module)?)?;
+    Ok(())
+}
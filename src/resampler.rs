@@ -0,0 +1,83 @@
+// Streaming sample rate conversion, used to run DTLN at 16khz inside
+// pipelines operating at other rates.
+//
+// Every output sample is interpolated from the surrounding input samples with
+// a Blackman windowed sinc kernel. When downsampling, the kernel is widened to
+// low-pass the input below the new Nyquist frequency.
+
+// Kernel zero crossings on either side of an output sample.
+const ZERO_CROSSINGS: usize = 16;
+
+// Fraction of the lower Nyquist frequency kept, leaving room for the
+// transition band.
+const CUTOFF: f64 = 0.95;
+
+pub struct Resampler {
+    // Input samples per output sample.
+    step: f64,
+    // Kernel bandwidth relative to the input Nyquist frequency.
+    cutoff: f64,
+    // Input samples on either side of an output sample used by the kernel.
+    half_taps: usize,
+    // Input not yet fully consumed, preceded by half_taps samples of history.
+    buffer: Vec<f32>,
+    // Position of the next output sample within buffer.
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let step = from_rate as f64 / to_rate as f64;
+        let cutoff = CUTOFF * (1.0 / step).min(1.0);
+        let half_taps = (ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+        Resampler {
+            step,
+            cutoff,
+            half_taps,
+            buffer: vec![0.0; half_taps],
+            position: 0.0,
+        }
+    }
+
+    /**
+     * Delay added by the resampler, in input samples.
+     */
+    pub fn latency(&self) -> usize {
+        self.half_taps
+    }
+
+    /**
+     * Convert the next input samples, appending the result to output.
+     */
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.buffer.extend_from_slice(input);
+
+        while (self.position as usize) + self.half_taps < self.buffer.len() {
+            let center = self.position as usize;
+            let mut sum = 0.0;
+            for k in (center + 1).saturating_sub(self.half_taps)..=center + self.half_taps {
+                let distance = self.position - k as f64;
+                sum += self.buffer[k] as f64 * self.kernel(distance);
+            }
+            output.push(sum as f32);
+            self.position += self.step;
+        }
+
+        // Drop input which no future output sample reaches.
+        let consumed = ((self.position as usize) + 1).saturating_sub(self.half_taps);
+        self.buffer.drain(..consumed.min(self.buffer.len()));
+        self.position -= consumed as f64;
+    }
+
+    fn kernel(&self, distance: f64) -> f64 {
+        let width = self.half_taps as f64;
+        if distance.abs() >= width {
+            return 0.0;
+        }
+        let x = std::f64::consts::PI * self.cutoff * distance;
+        let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+        let phase = std::f64::consts::PI * (distance / width + 1.0);
+        let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+        self.cutoff * sinc * window
+    }
+}
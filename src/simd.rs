@@ -0,0 +1,245 @@
+// Hand-written SIMD kernels for the native DTLN inference path.
+//
+// Every kernel has a scalar fallback. AVX2/FMA is selected at runtime on
+// x86_64, NEON is always available on aarch64, and wasm32 uses simd128 when
+// it is enabled at compile time.
+
+/**
+ * Apply the same matrix to a batch of vectors stored back to back:
+ * out[n * rows + r] = sum(weights[r * cols + c] * inputs[n * cols + c]).
+ *
+ * Each weight row is reused for the whole batch while it is still in cache,
+ * which is where batching several streams gains its throughput.
+ *
+ * @param weights Row-major matrix with `cols` columns.
+ * @param inputs Batch of vectors of length `cols`.
+ * @param out Batch of vectors of length weights.len() / cols.
+ */
+pub fn matmul(weights: &[f32], cols: usize, inputs: &[f32], out: &mut [f32]) {
+    let rows = weights.len() / cols;
+    assert_eq!(weights.len(), rows * cols);
+    assert_eq!(inputs.len() / cols, out.len() / rows);
+    assert_eq!(inputs.len() % cols, 0);
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        unsafe { avx2::matmul(weights, cols, inputs, out) };
+        return;
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        unsafe { neon::matmul(weights, cols, inputs, out) };
+        return;
+    }
+
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    {
+        unsafe { wasm_simd::matmul(weights, cols, inputs, out) };
+        return;
+    }
+
+    #[allow(unreachable_code)]
+    scalar::matmul(weights, cols, inputs, out);
+}
+
+/**
+ * Elementwise accumulate: acc[i] += values[i].
+ */
+pub fn add_assign(acc: &mut [f32], values: &[f32]) {
+    for (a, v) in acc.iter_mut().zip(values) {
+        *a += v;
+    }
+}
+
+pub fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+mod scalar {
+    pub fn matmul(weights: &[f32], cols: usize, inputs: &[f32], out: &mut [f32]) {
+        let rows = weights.len() / cols;
+        for (r, row) in weights.chunks_exact(cols).enumerate() {
+            for (n, input) in inputs.chunks_exact(cols).enumerate() {
+                out[n * rows + r] = row.iter().zip(input).map(|(w, x)| w * x).sum();
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn hsum(v: __m256) -> f32 {
+        let low = _mm256_castps256_ps128(v);
+        let high = _mm256_extractf128_ps(v, 1);
+        let sum = _mm_add_ps(low, high);
+        let sum = _mm_add_ps(sum, _mm_movehl_ps(sum, sum));
+        let sum = _mm_add_ss(sum, _mm_shuffle_ps(sum, sum, 1));
+        _mm_cvtss_f32(sum)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn dot(row: *const f32, x: *const f32, cols: usize) -> f32 {
+        let vector_cols = cols - cols % 16;
+
+        // Two accumulators hide the FMA latency.
+        let mut acc0 = _mm256_setzero_ps();
+        let mut acc1 = _mm256_setzero_ps();
+        let mut c = 0;
+        while c < vector_cols {
+            acc0 = _mm256_fmadd_ps(_mm256_loadu_ps(row.add(c)), _mm256_loadu_ps(x.add(c)), acc0);
+            acc1 = _mm256_fmadd_ps(
+                _mm256_loadu_ps(row.add(c + 8)),
+                _mm256_loadu_ps(x.add(c + 8)),
+                acc1,
+            );
+            c += 16;
+        }
+
+        let mut sum = hsum(_mm256_add_ps(acc0, acc1));
+        while c < cols {
+            sum += *row.add(c) * *x.add(c);
+            c += 1;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn matmul(weights: &[f32], cols: usize, inputs: &[f32], out: &mut [f32]) {
+        let rows = weights.len() / cols;
+        let batch = inputs.len() / cols;
+        for r in 0..rows {
+            let row = weights.as_ptr().add(r * cols);
+            for n in 0..batch {
+                out[n * rows + r] = dot(row, inputs.as_ptr().add(n * cols), cols);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    unsafe fn dot(row: *const f32, x: *const f32, cols: usize) -> f32 {
+        let vector_cols = cols - cols % 8;
+
+        let mut acc0 = vdupq_n_f32(0.0);
+        let mut acc1 = vdupq_n_f32(0.0);
+        let mut c = 0;
+        while c < vector_cols {
+            acc0 = vfmaq_f32(acc0, vld1q_f32(row.add(c)), vld1q_f32(x.add(c)));
+            acc1 = vfmaq_f32(acc1, vld1q_f32(row.add(c + 4)), vld1q_f32(x.add(c + 4)));
+            c += 8;
+        }
+
+        let mut sum = vaddvq_f32(vaddq_f32(acc0, acc1));
+        while c < cols {
+            sum += *row.add(c) * *x.add(c);
+            c += 1;
+        }
+        sum
+    }
+
+    pub unsafe fn matmul(weights: &[f32], cols: usize, inputs: &[f32], out: &mut [f32]) {
+        let rows = weights.len() / cols;
+        let batch = inputs.len() / cols;
+        for r in 0..rows {
+            let row = weights.as_ptr().add(r * cols);
+            for n in 0..batch {
+                out[n * rows + r] = dot(row, inputs.as_ptr().add(n * cols), cols);
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod wasm_simd {
+    use std::arch::wasm32::*;
+
+    unsafe fn dot(row: *const f32, x: *const f32, cols: usize) -> f32 {
+        let vector_cols = cols - cols % 8;
+
+        // simd128 has no fused multiply-add.
+        let mut acc0 = f32x4_splat(0.0);
+        let mut acc1 = f32x4_splat(0.0);
+        let mut c = 0;
+        while c < vector_cols {
+            let w0 = v128_load(row.add(c) as *const v128);
+            let w1 = v128_load(row.add(c + 4) as *const v128);
+            acc0 = f32x4_add(acc0, f32x4_mul(w0, v128_load(x.add(c) as *const v128)));
+            acc1 = f32x4_add(acc1, f32x4_mul(w1, v128_load(x.add(c + 4) as *const v128)));
+            c += 8;
+        }
+
+        let acc = f32x4_add(acc0, acc1);
+        let mut sum = f32x4_extract_lane::<0>(acc)
+            + f32x4_extract_lane::<1>(acc)
+            + f32x4_extract_lane::<2>(acc)
+            + f32x4_extract_lane::<3>(acc);
+        while c < cols {
+            sum += *row.add(c) * *x.add(c);
+            c += 1;
+        }
+        sum
+    }
+
+    pub unsafe fn matmul(weights: &[f32], cols: usize, inputs: &[f32], out: &mut [f32]) {
+        let rows = weights.len() / cols;
+        let batch = inputs.len() / cols;
+        for r in 0..rows {
+            let row = weights.as_ptr().add(r * cols);
+            for n in 0..batch {
+                out[n * rows + r] = dot(row, inputs.as_ptr().add(n * cols), cols);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Kernel = unsafe fn(&[f32], usize, &[f32], &mut [f32]);
+
+    // Compare a kernel against the scalar one, with column counts around the
+    // 8 and 16 lane unrolls so the remainder loops are exercised.
+    fn check_kernel(kernel: Kernel) {
+        let (rows, batch) = (5, 3);
+        for cols in [1, 3, 7, 8, 9, 15, 17, 31, 33, 257] {
+            let weights = (0..rows * cols)
+                .map(|i| ((i * 7919) % 101) as f32 / 101.0 - 0.5)
+                .collect::<Vec<f32>>();
+            let inputs = (0..batch * cols)
+                .map(|i| ((i * 104729) % 89) as f32 / 89.0 - 0.5)
+                .collect::<Vec<f32>>();
+
+            let mut expected = vec![0.0; rows * batch];
+            scalar::matmul(&weights, cols, &inputs, &mut expected);
+            let mut out = vec![0.0; rows * batch];
+            unsafe { kernel(&weights, cols, &inputs, &mut out) };
+
+            for (o, e) in out.iter().zip(&expected) {
+                assert!((o - e).abs() < 1e-4, "{} columns: {} != {}", cols, o, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_kernels_match_scalar() {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+            check_kernel(avx2::matmul);
+        }
+        #[cfg(target_arch = "aarch64")]
+        check_kernel(neon::matmul);
+        #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+        check_kernel(wasm_simd::matmul);
+
+        // The dispatch, with whichever kernel it selects.
+        check_kernel(matmul);
+    }
+}
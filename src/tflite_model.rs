@@ -0,0 +1,190 @@
+// Minimal reader for the TFLite flatbuffer format.
+//
+// Only the parts of the schema needed to pull constant float32 weights out of
+// the bundled DTLN models are decoded. See tensorflow/lite/schema/schema.fbs.
+
+use anyhow::{anyhow, Context, Result};
+
+const TENSOR_TYPE_FLOAT32: u8 = 0;
+
+// A constant float32 tensor stored in the model's buffers.
+pub struct ConstTensor {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub data: Vec<f32>,
+}
+
+// Bounds checked view over the raw flatbuffer bytes.
+struct FlatBuffer<'a> {
+    data: &'a [u8],
+}
+
+#[derive(Clone, Copy)]
+struct Table {
+    pos: usize,
+    vtable: usize,
+    vtable_len: usize,
+}
+
+impl<'a> FlatBuffer<'a> {
+    fn read<const N: usize>(&self, pos: usize) -> Result<[u8; N]> {
+        self.data
+            .get(pos..pos + N)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| anyhow!("Flatbuffer read out of bounds at {}", pos))
+    }
+
+    fn u16(&self, pos: usize) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.read(pos)?))
+    }
+
+    fn u32(&self, pos: usize) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read(pos)?))
+    }
+
+    fn i32(&self, pos: usize) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.read(pos)?))
+    }
+
+    // Follow a uoffset_t stored at `pos`.
+    fn indirect(&self, pos: usize) -> Result<usize> {
+        Ok(pos + self.u32(pos)? as usize)
+    }
+
+    fn table(&self, pos: usize) -> Result<Table> {
+        let vtable = (pos as i64 - self.i32(pos)? as i64) as usize;
+        let vtable_len = self.u16(vtable)? as usize;
+        Ok(Table {
+            pos,
+            vtable,
+            vtable_len,
+        })
+    }
+
+    // Position of field `index` within `table`, or None if it is not present.
+    fn field(&self, table: Table, index: usize) -> Result<Option<usize>> {
+        let entry = 4 + 2 * index;
+        if entry >= table.vtable_len {
+            return Ok(None);
+        }
+        let offset = self.u16(table.vtable + entry)? as usize;
+        Ok((offset != 0).then_some(table.pos + offset))
+    }
+
+    // Returns (position of first element, element count) for a vector field.
+    fn vector(&self, table: Table, index: usize) -> Result<(usize, usize)> {
+        match self.field(table, index)? {
+            Some(pos) => {
+                let start = self.indirect(pos)?;
+                Ok((start + 4, self.u32(start)? as usize))
+            }
+            None => Ok((0, 0)),
+        }
+    }
+
+    fn table_vector(&self, table: Table, index: usize) -> Result<Vec<Table>> {
+        let (start, len) = self.vector(table, index)?;
+        (0..len)
+            .map(|i| self.table(self.indirect(start + 4 * i)?))
+            .collect()
+    }
+
+    fn i32_vector(&self, table: Table, index: usize) -> Result<Vec<i32>> {
+        let (start, len) = self.vector(table, index)?;
+        (0..len).map(|i| self.i32(start + 4 * i)).collect()
+    }
+
+    fn string(&self, table: Table, index: usize) -> Result<String> {
+        let (start, len) = self.vector(table, index)?;
+        let bytes = self
+            .data
+            .get(start..start + len)
+            .ok_or_else(|| anyhow!("Flatbuffer string out of bounds at {}", start))?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn scalar_u8(&self, table: Table, index: usize) -> Result<u8> {
+        match self.field(table, index)? {
+            Some(pos) => Ok(self.read::<1>(pos)?[0]),
+            None => Ok(0),
+        }
+    }
+
+    fn scalar_u32(&self, table: Table, index: usize) -> Result<u32> {
+        match self.field(table, index)? {
+            Some(pos) => self.u32(pos),
+            None => Ok(0),
+        }
+    }
+}
+
+/**
+ * Read the constant float32 tensors consumed by the operators of the first
+ * subgraph, in the order the operators reference them. A tensor referenced by
+ * several operators is only returned once.
+ *
+ * Weights for the DTLN models are laid out the way TFLite stores them, i.e.
+ * FULLY_CONNECTED weights are [out, in] and CONV_2D weights [out, 1, 1, in].
+ */
+pub fn read_const_tensors(model: &[u8]) -> Result<Vec<ConstTensor>> {
+    let fb = FlatBuffer { data: model };
+
+    let root = fb.table(fb.indirect(0)?)?;
+    let buffers = fb.table_vector(root, 4)?;
+    let subgraph = *fb
+        .table_vector(root, 2)?
+        .first()
+        .context("Model has no subgraphs")?;
+    let tensors = fb.table_vector(subgraph, 0)?;
+    let operators = fb.table_vector(subgraph, 3)?;
+
+    let mut seen = vec![false; tensors.len()];
+    let mut result = vec![];
+
+    for operator in operators {
+        for input in fb.i32_vector(operator, 1)? {
+            // Optional inputs (e.g. a missing bias) are encoded as -1.
+            let Ok(index) = usize::try_from(input) else {
+                continue;
+            };
+            let tensor = *tensors
+                .get(index)
+                .ok_or_else(|| anyhow!("Tensor index {} out of range", index))?;
+            if seen[index] || fb.scalar_u8(tensor, 1)? != TENSOR_TYPE_FLOAT32 {
+                continue;
+            }
+
+            let buffer_index = fb.scalar_u32(tensor, 2)? as usize;
+            let buffer = *buffers
+                .get(buffer_index)
+                .ok_or_else(|| anyhow!("Buffer index {} out of range", buffer_index))?;
+            let (start, len) = fb.vector(buffer, 0)?;
+            if len == 0 {
+                // Not a constant, the data is produced at runtime.
+                continue;
+            }
+
+            let bytes = model
+                .get(start..start + len)
+                .ok_or_else(|| anyhow!("Buffer {} out of bounds", buffer_index))?;
+            let data = bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            let shape = fb
+                .i32_vector(tensor, 0)?
+                .into_iter()
+                .map(|dim| dim as usize)
+                .collect();
+
+            seen[index] = true;
+            result.push(ConstTensor {
+                name: fb.string(tensor, 3)?,
+                shape,
+                data,
+            });
+        }
+    }
+
+    Ok(result)
+}
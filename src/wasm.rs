@@ -1,25 +1,267 @@
 // Define webassembly interface to the library
+use dtln_rs::dtln_agc::AgcConfig;
+use dtln_rs::dtln_filters::PreFilterConfig;
+use dtln_rs::dtln_limiter::LimiterConfig;
+use dtln_rs::dtln_processor::ProcessorOptions;
 use dtln_rs::dtln_utilities::{
-    dtln_create_global, dtln_denoise_global, dtln_destroy_global, dtln_get_audio_buffer_raw_ptr,
+    dtln_clip_stats_global, dtln_create_global, dtln_denoise_global, dtln_destroy_global,
+    dtln_frame_size_global, dtln_get_input_buffer_raw_ptr, dtln_get_output_buffer_raw_ptr,
+    dtln_latency_global, dtln_pipeline_create_global, dtln_pipeline_destroy_global,
+    dtln_pipeline_get_buffer_raw_ptr, dtln_pipeline_latency_global, dtln_pipeline_process_global,
+    dtln_reset_global, dtln_set_agc_global, dtln_set_frame_size_global, dtln_set_limiter_global,
+    dtln_set_min_gain_db_global, dtln_set_mix_global, dtln_set_pre_filter_global,
+    dtln_stats_global, dtln_vad_global,
 };
 
 // WASM Interface/exports.
+
+// A min_gain_db which is NaN keeps the default. Returns -1 if the engine
+// can't be created.
+#[no_mangle]
+extern "C" fn dtln_create_wasm(sample_rate: u32, native: bool, min_gain_db: f32) -> i32 {
+    let options = ProcessorOptions {
+        sample_rate,
+        native,
+        min_gain_db: (!min_gain_db.is_nan()).then_some(min_gain_db),
+        ..ProcessorOptions::default()
+    };
+    match dtln_create_global(options) {
+        Ok(id) => id as i32,
+        Err(e) => {
+            eprintln!("[DTLN] Failed to create engine: {:#}", e);
+            -1
+        }
+    }
+}
+
 #[no_mangle]
-extern "C" fn dtln_create_wasm() -> u32 {
-    dtln_create_global()
+extern "C" fn dtln_set_frame_size_wasm(id: u32, frame_size: u32) {
+    dtln_set_frame_size_global(id, frame_size as usize);
 }
 
 #[no_mangle]
-extern "C" fn dtln_get_audio_buffer(id: u32) -> *const f32 {
-    dtln_get_audio_buffer_raw_ptr(id)
+extern "C" fn dtln_frame_size_wasm(id: u32) -> u32 {
+    dtln_frame_size_global(id) as u32
 }
 
 #[no_mangle]
-extern "C" fn dtln_denoise_wasm(id: u32) {
-    let _ = dtln_denoise_global(id);
+extern "C" fn dtln_get_input_buffer(id: u32) -> *mut f32 {
+    dtln_get_input_buffer_raw_ptr(id)
+}
+
+#[no_mangle]
+extern "C" fn dtln_get_output_buffer(id: u32) -> *const f32 {
+    dtln_get_output_buffer_raw_ptr(id)
+}
+
+// Returns true if the processor is backed up, which immediate processing
+// never is.
+#[no_mangle]
+extern "C" fn dtln_denoise_wasm(id: u32, sample_count: u32) -> bool {
+    match dtln_denoise_global(id, sample_count as usize) {
+        Ok(starved) => starved,
+        Err(e) => {
+            eprintln!("[DTLN] Failed to denoise: {}", e);
+            false
+        }
+    }
 }
 
 #[no_mangle]
 extern "C" fn dtln_destroy_wasm(id: u32) {
     dtln_destroy_global(id);
 }
+
+#[no_mangle]
+extern "C" fn dtln_reset_wasm(id: u32) {
+    dtln_reset_global(id);
+}
+
+#[no_mangle]
+extern "C" fn dtln_latency_wasm(id: u32) -> u32 {
+    dtln_latency_global(id) as u32
+}
+
+// Statistics are returned as f64 like the clipping counters, times in ms.
+#[no_mangle]
+extern "C" fn dtln_get_calls_wasm(id: u32) -> f64 {
+    dtln_stats_global(id, false).calls as f64
+}
+
+#[no_mangle]
+extern "C" fn dtln_get_samples_wasm(id: u32) -> f64 {
+    dtln_stats_global(id, false).samples as f64
+}
+
+#[no_mangle]
+extern "C" fn dtln_get_processing_time_wasm(id: u32) -> f64 {
+    dtln_stats_global(id, false).processing_time.as_secs_f64() * 1000.0
+}
+
+#[no_mangle]
+extern "C" fn dtln_get_max_processing_time_wasm(id: u32) -> f64 {
+    dtln_stats_global(id, false)
+        .max_processing_time
+        .as_secs_f64()
+        * 1000.0
+}
+
+#[no_mangle]
+extern "C" fn dtln_reset_stats_wasm(id: u32) {
+    dtln_stats_global(id, true);
+}
+
+#[no_mangle]
+extern "C" fn dtln_set_min_gain_db_wasm(id: u32, min_gain_db: f32) {
+    dtln_set_min_gain_db_global(id, min_gain_db);
+}
+
+#[no_mangle]
+extern "C" fn dtln_set_mix_wasm(id: u32, wet: f32) {
+    dtln_set_mix_global(id, wet);
+}
+
+#[no_mangle]
+extern "C" fn dtln_get_speech_probability_wasm(id: u32) -> f32 {
+    dtln_vad_global(id).speech_probability
+}
+
+#[no_mangle]
+extern "C" fn dtln_is_speech_wasm(id: u32) -> bool {
+    dtln_vad_global(id).is_speech
+}
+
+// NaN parameters keep their default value.
+#[no_mangle]
+extern "C" fn dtln_set_agc_wasm(
+    id: u32,
+    enabled: bool,
+    target_level_db: f32,
+    max_gain_db: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    vad_gated: bool,
+) {
+    let config = enabled.then(|| {
+        let defaults = AgcConfig::default();
+        let or_default = |value: f32, default: f32| if value.is_nan() { default } else { value };
+        AgcConfig {
+            target_level_db: or_default(target_level_db, defaults.target_level_db),
+            max_gain_db: or_default(max_gain_db, defaults.max_gain_db),
+            attack_ms: or_default(attack_ms, defaults.attack_ms),
+            release_ms: or_default(release_ms, defaults.release_ms),
+            vad_gated,
+        }
+    });
+    dtln_set_agc_global(id, config);
+}
+
+// NaN parameters keep their default value.
+#[no_mangle]
+extern "C" fn dtln_set_limiter_wasm(
+    id: u32,
+    enabled: bool,
+    ceiling_db: f32,
+    lookahead_ms: f32,
+    release_ms: f32,
+) {
+    let config = enabled.then(|| {
+        let defaults = LimiterConfig::default();
+        let or_default = |value: f32, default: f32| if value.is_nan() { default } else { value };
+        LimiterConfig {
+            ceiling_db: or_default(ceiling_db, defaults.ceiling_db),
+            lookahead_ms: or_default(lookahead_ms, defaults.lookahead_ms),
+            release_ms: or_default(release_ms, defaults.release_ms),
+        }
+    });
+    dtln_set_limiter_global(id, config);
+}
+
+// Counters are returned as f64, which JavaScript numbers represent exactly.
+#[no_mangle]
+extern "C" fn dtln_get_clipped_samples_wasm(id: u32) -> f64 {
+    dtln_clip_stats_global(id, false).clipped_samples as f64
+}
+
+#[no_mangle]
+extern "C" fn dtln_get_limited_samples_wasm(id: u32) -> f64 {
+    dtln_clip_stats_global(id, false).limited_samples as f64
+}
+
+#[no_mangle]
+extern "C" fn dtln_reset_clip_stats_wasm(id: u32) {
+    dtln_clip_stats_global(id, true);
+}
+
+// A high_pass_hz which is NaN or not positive disables the high-pass filter.
+#[no_mangle]
+extern "C" fn dtln_set_pre_filter_wasm(id: u32, dc_blocker: bool, high_pass_hz: f32) {
+    dtln_set_pre_filter_global(
+        id,
+        PreFilterConfig {
+            dc_blocker,
+            high_pass_hz: (high_pass_hz > 0.0).then_some(high_pass_hz),
+        },
+    );
+}
+
+// Memory for passing strings from JavaScript, such as pipeline configs.
+#[no_mangle]
+extern "C" fn dtln_alloc_wasm(len: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; len].into_boxed_slice();
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+#[no_mangle]
+unsafe extern "C" fn dtln_free_wasm(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+// Returns -1 if the config is invalid.
+#[no_mangle]
+unsafe extern "C" fn dtln_pipeline_create_wasm(config: *const u8, len: usize) -> i32 {
+    let config = std::slice::from_raw_parts(config, len);
+    let Ok(config) = std::str::from_utf8(config) else {
+        eprintln!("[DTLN] Pipeline config is not valid UTF-8");
+        return -1;
+    };
+    match dtln_pipeline_create_global(config) {
+        Ok(id) => id as i32,
+        Err(e) => {
+            eprintln!("[DTLN] Failed to create pipeline: {:#}", e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn dtln_pipeline_destroy_wasm(id: u32) {
+    dtln_pipeline_destroy_global(id);
+}
+
+// Grows the pipeline buffer to frame_size samples if it is shorter, which
+// invalidates pointers returned before.
+#[no_mangle]
+extern "C" fn dtln_pipeline_get_buffer_wasm(id: u32, frame_size: usize) -> *mut f32 {
+    dtln_pipeline_get_buffer_raw_ptr(id, frame_size)
+}
+
+// Processes sample_count samples of the pipeline buffer in place. Returns
+// false if the buffer is shorter.
+#[no_mangle]
+extern "C" fn dtln_pipeline_process_wasm(id: u32, sample_count: usize) -> bool {
+    match dtln_pipeline_process_global(id, sample_count) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("[DTLN] Failed to process pipeline: {}", e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn dtln_pipeline_latency_wasm(id: u32) -> u32 {
+    dtln_pipeline_latency_global(id) as u32
+}
@@ -0,0 +1,385 @@
+// wasm-bindgen exports for wasm32-unknown-unknown, matching the interface of
+// the NEON module and dtln_post.js. There is no TFLite here, so the native
+// implementation is always used, and processing is always immediate.
+use std::time::Duration;
+
+use js_sys::{Function, Object, Reflect, JSON};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::audio_pipeline::{AudioPipeline, FrameAdapter};
+use crate::dtln_agc::AgcConfig;
+use crate::dtln_engine::DtlnEngine;
+use crate::dtln_filters::PreFilterConfig;
+use crate::dtln_limiter::LimiterConfig;
+use crate::dtln_processor::ProcessorStats;
+
+// DTLN sample rate.
+const SAMPLE_RATE: u32 = 16000;
+
+struct WebDenoiser {
+    engine: DtlnEngine,
+    // Resampling to 16khz and block buffering, so any length is accepted.
+    adapter: FrameAdapter,
+    stats: ProcessorStats,
+}
+
+/**
+ * A denoiser created by dtln_create. Destroying it frees the engine, the
+ * handle itself is freed by the garbage collector or free().
+ */
+#[wasm_bindgen]
+pub struct DtlnHandle {
+    denoiser: Option<WebDenoiser>,
+}
+
+impl DtlnHandle {
+    fn denoiser(&mut self) -> Result<&mut WebDenoiser, JsError> {
+        self.denoiser
+            .as_mut()
+            .ok_or_else(|| JsError::new("The denoiser was destroyed"))
+    }
+}
+
+#[wasm_bindgen]
+pub struct DtlnPipelineHandle {
+    pipeline: AudioPipeline,
+}
+
+/**
+ * Create a denoiser.
+ * @param options Optional, any of sampleRate (16000 by default) and
+ * minGainDb. mode, threads and native are accepted and ignored.
+ */
+#[wasm_bindgen]
+pub fn dtln_create(options: JsValue) -> Result<DtlnHandle, JsError> {
+    let sample_rate = optional_number(&options, "sampleRate").map_or(SAMPLE_RATE, |v| v as u32);
+    let mut engine =
+        DtlnEngine::new_native().ok_or_else(|| JsError::new("Unable to create engine"))?;
+    if let Some(min_gain_db) = optional_number(&options, "minGainDb") {
+        engine.set_min_gain_db(min_gain_db);
+        // Start at the configured level instead of ramping to it.
+        engine.reset();
+    }
+    let adapter = FrameAdapter::new(sample_rate).map_err(|e| JsError::new(&format!("{:#}", e)))?;
+
+    Ok(DtlnHandle {
+        denoiser: Some(WebDenoiser {
+            engine,
+            adapter,
+            stats: ProcessorStats::default(),
+        }),
+    })
+}
+
+/**
+ * Free the engine. Calling it again has no effect.
+ */
+#[wasm_bindgen]
+pub fn dtln_destroy(handle: &mut DtlnHandle) {
+    handle.denoiser = None;
+}
+
+/**
+ * The same as dtln_destroy.
+ */
+#[wasm_bindgen]
+pub fn dtln_stop(handle: &mut DtlnHandle) {
+    dtln_destroy(handle);
+}
+
+/**
+ * Denoise samples of any length into output, delayed by dtln_latency.
+ * @returns Always false, immediate processing is never backed up.
+ */
+#[wasm_bindgen]
+pub fn dtln_denoise(
+    handle: &mut DtlnHandle,
+    samples: &[f32],
+    output: &mut [f32],
+) -> Result<bool, JsError> {
+    let denoiser = handle.denoiser()?;
+    if output.len() < samples.len() {
+        return Err(JsError::new(&format!(
+            "Output buffer too small, {} vs {}",
+            output.len(),
+            samples.len()
+        )));
+    }
+
+    let start = now_ms();
+    let engine = &mut denoiser.engine;
+    denoiser.adapter.process(samples, output, |frames| {
+        let input = frames.clone();
+        engine.denoise(&input, frames);
+    });
+    let processing_time = Duration::from_secs_f64((now_ms() - start).max(0.0) / 1000.0);
+
+    let stats = &mut denoiser.stats;
+    stats.calls += 1;
+    stats.samples += samples.len() as u64;
+    stats.processing_time += processing_time;
+    stats.max_processing_time = stats.max_processing_time.max(processing_time);
+    Ok(false)
+}
+
+/**
+ * Return the denoiser to its initial state, keeping its settings.
+ */
+#[wasm_bindgen]
+pub fn dtln_reset(handle: &mut DtlnHandle) -> Result<(), JsError> {
+    let denoiser = handle.denoiser()?;
+    denoiser.engine.reset();
+    denoiser.adapter.reset();
+    Ok(())
+}
+
+/**
+ * Delay of the output in samples, at the denoiser sample rate.
+ */
+#[wasm_bindgen]
+pub fn dtln_latency(handle: &mut DtlnHandle) -> Result<u32, JsError> {
+    let denoiser = handle.denoiser()?;
+    Ok(denoiser.adapter.latency(denoiser.engine.latency()) as u32)
+}
+
+/**
+ * Processing statistics, optionally resetting them.
+ * @returns {calls, samples, starvedCalls, processingTimeMs,
+ * maxProcessingTimeMs, realtimeFactor}
+ */
+#[wasm_bindgen]
+pub fn dtln_stats(handle: &mut DtlnHandle, reset: Option<bool>) -> Result<Object, JsError> {
+    let denoiser = handle.denoiser()?;
+    let stats = denoiser.stats;
+    if reset.unwrap_or(false) {
+        denoiser.stats = ProcessorStats::default();
+    }
+
+    let audio_seconds = stats.samples as f64 / denoiser.adapter.sample_rate() as f64;
+    let processing_seconds = stats.processing_time.as_secs_f64();
+    let realtime_factor = if audio_seconds > 0.0 {
+        processing_seconds / audio_seconds
+    } else {
+        0.0
+    };
+
+    object(&[
+        ("calls", stats.calls as f64),
+        ("samples", stats.samples as f64),
+        ("starvedCalls", stats.starved_calls as f64),
+        ("processingTimeMs", processing_seconds * 1000.0),
+        (
+            "maxProcessingTimeMs",
+            stats.max_processing_time.as_secs_f64() * 1000.0,
+        ),
+        ("realtimeFactor", realtime_factor),
+    ])
+}
+
+#[wasm_bindgen]
+pub fn dtln_set_min_gain_db(handle: &mut DtlnHandle, min_gain_db: f32) -> Result<(), JsError> {
+    handle.denoiser()?.engine.set_min_gain_db(min_gain_db);
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn dtln_set_mix(handle: &mut DtlnHandle, wet: f32) -> Result<(), JsError> {
+    handle.denoiser()?.engine.set_mix(wet);
+    Ok(())
+}
+
+/**
+ * Voice activity of the samples returned by the most recent dtln_denoise call.
+ * @returns {speechProbability, isSpeech}
+ */
+#[wasm_bindgen]
+pub fn dtln_vad(handle: &mut DtlnHandle) -> Result<Object, JsError> {
+    let vad = handle.denoiser()?.engine.vad_result();
+    let result = object(&[("speechProbability", vad.speech_probability as f64)])?;
+    set(&result, "isSpeech", &JsValue::from_bool(vad.is_speech))?;
+    Ok(result)
+}
+
+/**
+ * @param options null disables the AGC. Otherwise any of targetLevelDb,
+ * maxGainDb, attackMs, releaseMs and vadGated.
+ */
+#[wasm_bindgen]
+pub fn dtln_set_agc(handle: &mut DtlnHandle, options: JsValue) -> Result<(), JsError> {
+    let config = options.is_object().then(|| {
+        let mut config = AgcConfig::default();
+        if let Some(value) = optional_number(&options, "targetLevelDb") {
+            config.target_level_db = value;
+        }
+        if let Some(value) = optional_number(&options, "maxGainDb") {
+            config.max_gain_db = value;
+        }
+        if let Some(value) = optional_number(&options, "attackMs") {
+            config.attack_ms = value;
+        }
+        if let Some(value) = optional_number(&options, "releaseMs") {
+            config.release_ms = value;
+        }
+        if let Some(value) = optional_bool(&options, "vadGated") {
+            config.vad_gated = value;
+        }
+        config
+    });
+    handle.denoiser()?.engine.set_agc(config);
+    Ok(())
+}
+
+/**
+ * @param options null disables the limiter. Otherwise any of ceilingDb,
+ * lookaheadMs and releaseMs.
+ */
+#[wasm_bindgen]
+pub fn dtln_set_limiter(handle: &mut DtlnHandle, options: JsValue) -> Result<(), JsError> {
+    let config = options.is_object().then(|| {
+        let mut config = LimiterConfig::default();
+        if let Some(value) = optional_number(&options, "ceilingDb") {
+            config.ceiling_db = value;
+        }
+        if let Some(value) = optional_number(&options, "lookaheadMs") {
+            config.lookahead_ms = value;
+        }
+        if let Some(value) = optional_number(&options, "releaseMs") {
+            config.release_ms = value;
+        }
+        config
+    });
+    handle.denoiser()?.engine.set_limiter(config);
+    Ok(())
+}
+
+/**
+ * @param options Any of dcBlocker and highPassHz, omitted filters are disabled.
+ */
+#[wasm_bindgen]
+pub fn dtln_set_pre_filter(handle: &mut DtlnHandle, options: JsValue) -> Result<(), JsError> {
+    let config = PreFilterConfig {
+        dc_blocker: optional_bool(&options, "dcBlocker").unwrap_or(false),
+        high_pass_hz: optional_number(&options, "highPassHz"),
+    };
+    handle.denoiser()?.engine.set_pre_filter(config);
+    Ok(())
+}
+
+/**
+ * Clipping counters of the output, optionally resetting them.
+ * @returns {clippedSamples, limitedSamples}
+ */
+#[wasm_bindgen]
+pub fn dtln_clip_stats(handle: &mut DtlnHandle, reset: Option<bool>) -> Result<Object, JsError> {
+    let engine = &mut handle.denoiser()?.engine;
+    let stats = engine.clip_stats();
+    if reset.unwrap_or(false) {
+        engine.reset_clip_stats();
+    }
+    object(&[
+        ("clippedSamples", stats.clipped_samples as f64),
+        ("limitedSamples", stats.limited_samples as f64),
+    ])
+}
+
+/**
+ * Create an AudioPipeline.
+ * @param config A pipeline config object or its JSON, see audio_pipeline.rs.
+ */
+#[wasm_bindgen]
+pub fn dtln_pipeline_create(config: JsValue) -> Result<DtlnPipelineHandle, JsError> {
+    let json = match config.as_string() {
+        Some(json) => json,
+        None => JSON::stringify(&config)
+            .ok()
+            .and_then(|json| json.as_string())
+            .ok_or_else(|| JsError::new("The pipeline config is not JSON serializable"))?,
+    };
+    let pipeline = AudioPipeline::from_json(&json)
+        .map_err(|e| JsError::new(&format!("Failed to create pipeline: {:#}", e)))?;
+    Ok(DtlnPipelineHandle { pipeline })
+}
+
+/**
+ * Pipelines are freed by the garbage collector or free().
+ */
+#[wasm_bindgen]
+pub fn dtln_pipeline_destroy(_handle: &DtlnPipelineHandle) {}
+
+/**
+ * Process samples of any length, output receives samples.length samples
+ * delayed by dtln_pipeline_latency.
+ */
+#[wasm_bindgen]
+pub fn dtln_pipeline_process(
+    handle: &mut DtlnPipelineHandle,
+    samples: &[f32],
+    output: &mut [f32],
+) -> Result<(), JsError> {
+    if output.len() < samples.len() {
+        return Err(JsError::new(&format!(
+            "Output buffer too small, {} vs {}",
+            output.len(),
+            samples.len()
+        )));
+    }
+    handle
+        .pipeline
+        .process(samples, &mut output[..samples.len()]);
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn dtln_pipeline_latency(handle: &DtlnPipelineHandle) -> u32 {
+    handle.pipeline.latency() as u32
+}
+
+fn optional_number(object: &JsValue, key: &str) -> Option<f32> {
+    if !object.is_object() {
+        return None;
+    }
+    Reflect::get(object, &JsValue::from_str(key))
+        .ok()?
+        .as_f64()
+        .map(|value| value as f32)
+}
+
+fn optional_bool(object: &JsValue, key: &str) -> Option<bool> {
+    if !object.is_object() {
+        return None;
+    }
+    Reflect::get(object, &JsValue::from_str(key))
+        .ok()?
+        .as_bool()
+}
+
+fn set(object: &Object, key: &str, value: &JsValue) -> Result<(), JsError> {
+    Reflect::set(object, &JsValue::from_str(key), value)
+        .map(|_| ())
+        .map_err(|_| JsError::new(&format!("Unable to set {}", key)))
+}
+
+fn object(fields: &[(&str, f64)]) -> Result<Object, JsError> {
+    let result = Object::new();
+    for (key, value) in fields {
+        set(&result, key, &JsValue::from_f64(*value))?;
+    }
+    Ok(result)
+}
+
+// performance.now() in browsers, workers and Deno. std::time::Instant is not
+// available on wasm32-unknown-unknown.
+fn now_ms() -> f64 {
+    let performance = Reflect::get(&js_sys::global(), &JsValue::from_str("performance")).ok();
+    performance
+        .and_then(|performance| {
+            let now = Reflect::get(&performance, &JsValue::from_str("now")).ok()?;
+            now.dyn_into::<Function>()
+                .ok()?
+                .call0(&performance)
+                .ok()?
+                .as_f64()
+        })
+        .unwrap_or_else(js_sys::Date::now)
+}